@@ -0,0 +1,530 @@
+//! # Assembler
+//!
+//! Build Chip8 programs from the mnemonics of Cowgod's technical reference,
+//! the syntax [`crate::disasm`] writes:
+//!
+//! ```text
+//! ; Move a ball across the screen
+//! SPEED = 2
+//!
+//! start:
+//!     LD V0, 0
+//!     LD I, ball
+//! loop:
+//!     DRW V0, V1, ball_end - ball
+//!     ADD V0, SPEED
+//!     DRW V0, V1, ball_end - ball
+//!     JP loop
+//!
+//! ball:
+//!     sprite .##. #### #### .##.
+//! ball_end:
+//! ```
+//!
+//! Labels end with `:` and constants are defined with `=`. Numbers are
+//! decimal, hexadecimal (`#FF` or `0xFF`) or binary (`%1010` or `0b1010`),
+//! and values can add and subtract numbers, labels and constants. Besides the
+//! instructions, `db` writes bytes, `dw` writes big endian words and `sprite`
+//! writes a byte per row of `#` (on) and `.` (off) pixels, left aligned.
+//! Comments start with `;`.
+
+use crate::cpu::MEMORY_SIZE;
+use crate::disasm::START;
+use std::collections::HashMap;
+
+// Names which are operands, and cannot be labels or constants
+const RESERVED: [&str; 8] = ["I", "DT", "ST", "K", "F", "B", "HF", "R"];
+
+/// Assembled program.
+pub struct Program {
+    /// Bytes of the program, loaded at [`START`].
+    pub rom: Vec<u8>,
+    /// Labels and their addresses, in the order of the addresses.
+    pub labels: Vec<(String, u16)>,
+}
+
+impl Program {
+    /// Symbol file listing the labels, one `<name> = <address>` per line.
+    pub fn symbol_file(&self) -> String {
+        self.labels
+            .iter()
+            .map(|(name, address)| format!("{} = 0x{:03X}\n", name, address))
+            .collect()
+    }
+}
+
+enum Statement<'a> {
+    Instruction(&'a str, Vec<&'a str>),
+    Bytes(Vec<&'a str>),
+    Words(Vec<&'a str>),
+    Sprite(Vec<u8>),
+}
+
+impl Statement<'_> {
+    fn size(&self) -> usize {
+        match self {
+            Statement::Instruction(..) => 2,
+            Statement::Bytes(values) => values.len(),
+            Statement::Words(values) => values.len() * 2,
+            Statement::Sprite(rows) => rows.len(),
+        }
+    }
+}
+
+enum Symbol<'a> {
+    Label(u16),
+    Constant(&'a str),
+}
+
+enum Operand {
+    V(u16),
+    I,
+    // [I]
+    Memory,
+    Dt,
+    St,
+    K,
+    F,
+    B,
+    // Big font of the SUPER-CHIP
+    Hf,
+    // Flags of the SUPER-CHIP
+    R,
+    Value(i64),
+}
+
+/// Assemble a program, the errors telling their line.
+pub fn assemble(source: &str) -> Result<Program, String> {
+    let mut symbols = HashMap::new();
+    let mut statements = Vec::new();
+    let mut address = START as usize;
+
+    // Place the statements and labels, the values being computed once all
+    // the symbols are known
+    for (number, line) in source.lines().enumerate() {
+        let error = |message: String| format!("line {}: {}", number + 1, message);
+        let mut line = line.split(';').next().unwrap_or_default().trim();
+
+        if let Some((name, value)) = line.split_once('=') {
+            let name = name.trim();
+            if is_identifier(name) {
+                define(&mut symbols, name, Symbol::Constant(value.trim())).map_err(error)?;
+                continue;
+            }
+        }
+
+        if let Some((name, rest)) = line.split_once(':') {
+            let name = name.trim();
+            if is_identifier(name) {
+                define(&mut symbols, name, Symbol::Label(address as u16)).map_err(error)?;
+                line = rest.trim();
+            }
+        }
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let statement = parse_statement(line).map_err(error)?;
+        address += statement.size();
+        if address > MEMORY_SIZE {
+            return Err(error("the program does not fit in memory".to_string()));
+        }
+        statements.push((number, statement));
+    }
+
+    let assembler = Assembler { symbols };
+    let mut rom = Vec::new();
+    for (number, statement) in &statements {
+        assembler
+            .write(statement, &mut rom)
+            .map_err(|message| format!("line {}: {}", number + 1, message))?;
+    }
+
+    let mut labels: Vec<(String, u16)> = assembler
+        .symbols
+        .iter()
+        .filter_map(|(name, symbol)| match symbol {
+            Symbol::Label(address) => Some((name.to_string(), *address)),
+            Symbol::Constant(_) => None,
+        })
+        .collect();
+    labels.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+
+    Ok(Program { rom, labels })
+}
+
+/// Whether a name can be a label or a constant.
+pub fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn define<'a>(
+    symbols: &mut HashMap<&'a str, Symbol<'a>>,
+    name: &'a str,
+    symbol: Symbol<'a>,
+) -> Result<(), String> {
+    let upper = name.to_ascii_uppercase();
+    if RESERVED.contains(&upper.as_str()) || parse_register(&upper).is_some() {
+        return Err(format!("{} is a register name", name));
+    }
+    if symbols.insert(name, symbol).is_some() {
+        return Err(format!("{} is defined twice", name));
+    }
+    Ok(())
+}
+
+fn parse_statement(line: &str) -> Result<Statement<'_>, String> {
+    let (name, operands) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let operands: Vec<&str> = if operands.trim().is_empty() {
+        Vec::new()
+    } else {
+        operands.split(',').map(str::trim).collect()
+    };
+
+    Ok(match name.to_ascii_lowercase().as_str() {
+        "db" if !operands.is_empty() => Statement::Bytes(operands),
+        "dw" if !operands.is_empty() => Statement::Words(operands),
+        "db" | "dw" => return Err(format!("{} expects values", name)),
+        "sprite" => {
+            let rows = line[name.len()..]
+                .split_whitespace()
+                .map(parse_sprite_row)
+                .collect::<Result<Vec<u8>, String>>()?;
+            if rows.is_empty() {
+                return Err("sprite expects rows".to_string());
+            }
+            Statement::Sprite(rows)
+        }
+        _ => Statement::Instruction(name, operands),
+    })
+}
+
+fn parse_sprite_row(row: &str) -> Result<u8, String> {
+    if row.len() > 8 {
+        return Err(format!("sprite row wider than 8 pixels: {}", row));
+    }
+
+    row.chars()
+        .enumerate()
+        .try_fold(0, |byte, (index, pixel)| match pixel {
+            '#' => Ok(byte | (0x80 >> index)),
+            '.' => Ok(byte),
+            _ => Err(format!("invalid sprite row: {}", row)),
+        })
+}
+
+fn parse_register(name: &str) -> Option<u16> {
+    let index = name.strip_prefix(['V', 'v'])?;
+    match index.len() {
+        1 => u16::from_str_radix(index, 16).ok(),
+        _ => None,
+    }
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (digits, radix) = if let Some(digits) = text.strip_prefix('#') {
+        (digits, 16)
+    } else if let Some(digits) = text.strip_prefix("0x").or(text.strip_prefix("0X")) {
+        (digits, 16)
+    } else if let Some(digits) = text.strip_prefix('%') {
+        (digits, 2)
+    } else if let Some(digits) = text.strip_prefix("0b").or(text.strip_prefix("0B")) {
+        (digits, 2)
+    } else {
+        (text, 10)
+    };
+
+    i64::from_str_radix(digits, radix).ok()
+}
+
+struct Assembler<'a> {
+    symbols: HashMap<&'a str, Symbol<'a>>,
+}
+
+impl Assembler<'_> {
+    fn write(&self, statement: &Statement, rom: &mut Vec<u8>) -> Result<(), String> {
+        match statement {
+            Statement::Instruction(name, operands) => {
+                let operands = operands
+                    .iter()
+                    .map(|operand| self.operand(operand))
+                    .collect::<Result<Vec<Operand>, String>>()?;
+                rom.extend(encode(name, &operands)?.to_be_bytes());
+            }
+            Statement::Bytes(values) => {
+                for value in values {
+                    rom.push(byte(self.evaluate(value, &mut Vec::new())?)?);
+                }
+            }
+            Statement::Words(values) => {
+                for value in values {
+                    let value = self.evaluate(value, &mut Vec::new())?;
+                    let word = u16::try_from(value)
+                        .map_err(|_| format!("{} does not fit a word", value))?;
+                    rom.extend(word.to_be_bytes());
+                }
+            }
+            Statement::Sprite(rows) => rom.extend(rows),
+        }
+        Ok(())
+    }
+
+    fn operand(&self, text: &str) -> Result<Operand, String> {
+        let upper = text.to_ascii_uppercase();
+        if let Some(index) = parse_register(&upper) {
+            return Ok(Operand::V(index));
+        }
+
+        Ok(match upper.as_str() {
+            "I" => Operand::I,
+            "[I]" => Operand::Memory,
+            "DT" => Operand::Dt,
+            "ST" => Operand::St,
+            "K" => Operand::K,
+            "F" => Operand::F,
+            "B" => Operand::B,
+            "HF" => Operand::Hf,
+            "R" => Operand::R,
+            _ => Operand::Value(self.evaluate(text, &mut Vec::new())?),
+        })
+    }
+
+    /// Value of a sum of numbers and symbols, `resolving` being the
+    /// constants being computed, to detect the constants defined by
+    /// themselves.
+    fn evaluate<'a>(&'a self, text: &'a str, resolving: &mut Vec<&'a str>) -> Result<i64, String> {
+        if text.trim().is_empty() {
+            return Err("missing value".to_string());
+        }
+
+        let mut terms = Vec::new();
+        let mut sign = 1;
+        let mut start = 0;
+        for (index, c) in text.char_indices() {
+            if c == '+' || c == '-' {
+                terms.push((sign, text[start..index].trim()));
+                sign = if c == '-' { -1 } else { 1 };
+                start = index + 1;
+            }
+        }
+        terms.push((sign, text[start..].trim()));
+
+        let mut total = 0;
+        for (index, (sign, term)) in terms.into_iter().enumerate() {
+            // Only the first term can be empty, for a leading sign
+            if term.is_empty() && index == 0 {
+                continue;
+            }
+            total += sign * self.term(term, resolving)?;
+        }
+        Ok(total)
+    }
+
+    fn term<'a>(&'a self, term: &'a str, resolving: &mut Vec<&'a str>) -> Result<i64, String> {
+        if !is_identifier(term) {
+            return parse_number(term).ok_or(format!("invalid value: {}", term));
+        }
+
+        match self.symbols.get(term) {
+            Some(Symbol::Label(address)) => Ok(*address as i64),
+            Some(Symbol::Constant(_)) if resolving.contains(&term) => {
+                Err(format!("{} is defined by itself", term))
+            }
+            Some(Symbol::Constant(value)) => {
+                resolving.push(term);
+                let value = self.evaluate(value, resolving);
+                resolving.pop();
+                value
+            }
+            None => Err(format!("unknown symbol: {}", term)),
+        }
+    }
+}
+
+fn encode(name: &str, operands: &[Operand]) -> Result<u16, String> {
+    use Operand::*;
+
+    let name = name.to_ascii_uppercase();
+    let opcode = match (name.as_str(), operands) {
+        ("CLS", []) => 0x00E0,
+        ("RET", []) => 0x00EE,
+        ("SYS", [Value(nnn)]) => address(*nnn)?,
+        ("JP", [Value(nnn)]) => 0x1000 | address(*nnn)?,
+        ("JP", [V(0), Value(nnn)]) => 0xB000 | address(*nnn)?,
+        ("CALL", [Value(nnn)]) => 0x2000 | address(*nnn)?,
+        ("SE", [V(x), Value(nn)]) => 0x3000 | x << 8 | byte(*nn)? as u16,
+        ("SNE", [V(x), Value(nn)]) => 0x4000 | x << 8 | byte(*nn)? as u16,
+        ("SE", [V(x), V(y)]) => 0x5000 | x << 8 | y << 4,
+        ("LD", [V(x), Value(nn)]) => 0x6000 | x << 8 | byte(*nn)? as u16,
+        ("ADD", [V(x), Value(nn)]) => 0x7000 | x << 8 | byte(*nn)? as u16,
+        ("LD", [V(x), V(y)]) => 0x8000 | x << 8 | y << 4,
+        ("OR", [V(x), V(y)]) => 0x8001 | x << 8 | y << 4,
+        ("AND", [V(x), V(y)]) => 0x8002 | x << 8 | y << 4,
+        ("XOR", [V(x), V(y)]) => 0x8003 | x << 8 | y << 4,
+        ("ADD", [V(x), V(y)]) => 0x8004 | x << 8 | y << 4,
+        ("SUB", [V(x), V(y)]) => 0x8005 | x << 8 | y << 4,
+        ("SHR", [V(x)]) => 0x8006 | x << 8 | x << 4,
+        ("SHR", [V(x), V(y)]) => 0x8006 | x << 8 | y << 4,
+        ("SUBN", [V(x), V(y)]) => 0x8007 | x << 8 | y << 4,
+        ("SHL", [V(x)]) => 0x800E | x << 8 | x << 4,
+        ("SHL", [V(x), V(y)]) => 0x800E | x << 8 | y << 4,
+        ("SNE", [V(x), V(y)]) => 0x9000 | x << 8 | y << 4,
+        ("LD", [I, Value(nnn)]) => 0xA000 | address(*nnn)?,
+        ("RND", [V(x), Value(nn)]) => 0xC000 | x << 8 | byte(*nn)? as u16,
+        ("DRW", [V(x), V(y), Value(n)]) => 0xD000 | x << 8 | y << 4 | nibble(*n)?,
+        ("SKP", [V(x)]) => 0xE09E | x << 8,
+        ("SKNP", [V(x)]) => 0xE0A1 | x << 8,
+        ("LD", [V(x), Dt]) => 0xF007 | x << 8,
+        ("LD", [V(x), K]) => 0xF00A | x << 8,
+        ("LD", [Dt, V(x)]) => 0xF015 | x << 8,
+        ("LD", [St, V(x)]) => 0xF018 | x << 8,
+        ("ADD", [I, V(x)]) => 0xF01E | x << 8,
+        ("LD", [F, V(x)]) => 0xF029 | x << 8,
+        ("LD", [B, V(x)]) => 0xF033 | x << 8,
+        ("LD", [Memory, V(x)]) => 0xF055 | x << 8,
+        ("LD", [V(x), Memory]) => 0xF065 | x << 8,
+        ("SCD", [Value(n)]) => 0x00C0 | nibble(*n)?,
+        ("SCR", []) => 0x00FB,
+        ("SCL", []) => 0x00FC,
+        ("EXIT", []) => 0x00FD,
+        ("LOW", []) => 0x00FE,
+        ("HIGH", []) => 0x00FF,
+        ("LD", [Hf, V(x)]) => 0xF030 | x << 8,
+        ("LD", [R, V(x)]) => 0xF075 | x << 8,
+        ("LD", [V(x), R]) => 0xF085 | x << 8,
+        _ => return Err(format!("invalid instruction: {}", name)),
+    };
+
+    Ok(opcode)
+}
+
+fn address(value: i64) -> Result<u16, String> {
+    match value {
+        0..=0xFFF => Ok(value as u16),
+        // Hexadecimal would print negative values in two's complement
+        _ => Err(format!(
+            "address out of memory: {}{:X}",
+            if value < 0 { "-" } else { "" },
+            value.unsigned_abs()
+        )),
+    }
+}
+
+/// Bytes can also be written as negative numbers, in two's complement.
+fn byte(value: i64) -> Result<u8, String> {
+    match value {
+        -0x80..=0xFF => Ok(value as u8),
+        _ => Err(format!("{} does not fit a byte", value)),
+    }
+}
+
+fn nibble(value: i64) -> Result<u16, String> {
+    match value {
+        0..=0xF => Ok(value as u16),
+        _ => Err(format!("{} does not fit a nibble", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Jump;
+    use crate::disasm;
+
+    // An instruction of every form, as the disassembler writes them
+    const INSTRUCTIONS: [&str; 45] = [
+        "SYS #123",
+        "CLS",
+        "RET",
+        "JP #234",
+        "CALL #345",
+        "SE V1, #AB",
+        "SNE V2, #CD",
+        "SE V3, V4",
+        "LD V5, #EF",
+        "ADD V6, #01",
+        "LD V7, V8",
+        "OR V9, VA",
+        "AND VB, VC",
+        "XOR VD, VE",
+        "ADD VF, V0",
+        "SUB V1, V2",
+        "SHR V3, V4",
+        "SUBN V5, V6",
+        "SHL V7, V8",
+        "SNE V9, VA",
+        "LD I, #456",
+        "JP V0, #567",
+        "RND VB, #23",
+        "DRW VC, VD, 15",
+        "SKP VE",
+        "SKNP VF",
+        "LD V0, DT",
+        "LD V1, K",
+        "LD DT, V2",
+        "LD ST, V3",
+        "ADD I, V4",
+        "LD F, V5",
+        "LD B, V6",
+        "LD [I], V7",
+        "LD V8, [I]",
+        "SCD 5",
+        "SCR",
+        "SCL",
+        "EXIT",
+        "LOW",
+        "HIGH",
+        "LD HF, V9",
+        "LD R, VA",
+        "LD VB, R",
+        "DW #F123",
+    ];
+
+    #[test]
+    fn disassembles_what_it_assembles() {
+        let jp = Jump::new();
+        let mut actions = Vec::new();
+
+        for instruction in INSTRUCTIONS {
+            let program = assemble(instruction).unwrap();
+            let opcode = u16::from_be_bytes([program.rom[0], program.rom[1]]);
+            assert_eq!(disasm::disassemble(&jp, opcode), instruction);
+            actions.push(jp.get_action(opcode));
+        }
+
+        // Every instruction of the CPU is covered
+        actions.sort();
+        actions.dedup();
+        assert_eq!(actions, (0..44).collect::<Vec<u16>>());
+    }
+
+    #[test]
+    fn resolves_labels_and_constants() {
+        let program = assemble(
+            "speed = 2\n\
+             start:\n    ADD V0, speed\n    JP start + speed\n\
+             sprite:\n    sprite #..#",
+        )
+        .unwrap();
+
+        assert_eq!(program.rom, [0x70, 0x02, 0x12, 0x02, 0x90]);
+        assert_eq!(program.symbol_file(), "start = 0x200\nsprite = 0x204\n");
+    }
+    #[test]
+    fn reports_the_line_of_errors() {
+        let error = |source| assemble(source).err().unwrap();
+
+        assert_eq!(error("CLS\nJUMP #200"), "line 2: invalid instruction: JUMP");
+        assert_eq!(error("JP nowhere"), "line 1: unknown symbol: nowhere");
+        assert_eq!(error("LD V0, #100"), "line 1: 256 does not fit a byte");
+        assert_eq!(error("DRW V0, V1, 16"), "line 1: 16 does not fit a nibble");
+        assert_eq!(error("JP #1000"), "line 1: address out of memory: 1000");
+        assert_eq!(error("JP -1"), "line 1: address out of memory: -1");
+        assert_eq!(error("LD I, -#200"), "line 1: address out of memory: -200");
+    }
+}
@@ -0,0 +1,74 @@
+//! # Chip8 assembler
+//!
+//! Build a Chip8 program and the symbol file listing its labels.
+
+use chip8_emulator::asm;
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "Usage: chip8-asm [options] <source_path>
+
+Options:
+    -o, --output <path>
+                   path of the program (default: the source path with the
+                   `.ch8` extension), the symbol file being written next to
+                   it with the `.sym` extension";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (source_path, output) = match parse(&args) {
+        Ok(options) => options,
+        Err(error) => {
+            println!("{}", error);
+            println!("{}", USAGE);
+            return;
+        }
+    };
+
+    if let Err(error) = build(&source_path, &output) {
+        println!("{}", error);
+        std::process::exit(1);
+    }
+}
+
+fn parse(args: &[String]) -> Result<(PathBuf, PathBuf), String> {
+    let mut source_path = None;
+    let mut output = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => {
+                output = Some(PathBuf::from(args.next().ok_or("--output expects a path")?));
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option: {}", arg)),
+            _ if source_path.is_none() => source_path = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument: {}", arg)),
+        }
+    }
+
+    let source_path = source_path.ok_or("missing source path")?;
+    let output = output.unwrap_or_else(|| source_path.with_extension("ch8"));
+    Ok((source_path, output))
+}
+
+fn build(source_path: &Path, output: &Path) -> Result<(), String> {
+    let source = std::fs::read_to_string(source_path)
+        .map_err(|error| format!("cannot read {}: {}", source_path.display(), error))?;
+    let program =
+        asm::assemble(&source).map_err(|error| format!("{}: {}", source_path.display(), error))?;
+
+    let symbols_path = output.with_extension("sym");
+    std::fs::write(output, &program.rom)
+        .map_err(|error| format!("cannot write {}: {}", output.display(), error))?;
+    std::fs::write(&symbols_path, program.symbol_file())
+        .map_err(|error| format!("cannot write {}: {}", symbols_path.display(), error))?;
+
+    println!(
+        "{} bytes written to {}, {} labels to {}",
+        program.rom.len(),
+        output.display(),
+        program.labels.len(),
+        symbols_path.display()
+    );
+    Ok(())
+}
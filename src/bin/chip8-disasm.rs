@@ -0,0 +1,66 @@
+//! # Chip8 disassembler
+//!
+//! Print the listing of a Chip8 program.
+
+use chip8_emulator::disasm::{self, Syntax};
+use chip8_emulator::symbols::Symbols;
+
+const USAGE: &str = "Usage: chip8-disasm [options] <rom_path>
+
+Options:
+    --syntax <syntax>
+                   write the mnemonics in the `cowgod` (default) or `octo`
+                   syntax
+    --symbols <path>
+                   name the labels after a symbol file (default: the ROM
+                   path with the `.sym` extension, if it exists)";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (rom_path, syntax, symbols_path) = match parse(&args) {
+        Ok(options) => options,
+        Err(error) => {
+            println!("{}", error);
+            println!("{}", USAGE);
+            return;
+        }
+    };
+
+    let symbols = match Symbols::for_rom(&rom_path, symbols_path.as_deref()) {
+        Ok(symbols) => symbols,
+        Err(error) => {
+            println!("{}", error);
+            return;
+        }
+    };
+
+    match std::fs::read(&rom_path) {
+        Ok(rom) => print!("{}", disasm::listing(&rom, syntax, &symbols)),
+        Err(error) => println!("cannot read {}: {}", rom_path, error),
+    }
+}
+
+fn parse(args: &[String]) -> Result<(String, Syntax, Option<String>), String> {
+    let mut rom_path = None;
+    let mut syntax = Syntax::Cowgod;
+    let mut symbols = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--syntax" => {
+                let value = args.next().ok_or("--syntax expects a syntax")?;
+                syntax = Syntax::parse(value).ok_or(format!("invalid syntax: {}", value))?;
+            }
+            "--symbols" => {
+                let path = args.next().ok_or("--symbols expects a path")?;
+                symbols = Some(path.clone());
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option: {}", arg)),
+            _ if rom_path.is_none() => rom_path = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument: {}", arg)),
+        }
+    }
+
+    Ok((rom_path.ok_or("missing rom path")?, syntax, symbols))
+}
@@ -0,0 +1,321 @@
+//! # Coverage
+//!
+//! Count how many times each address of the memory is executed, read and
+//! written, and save the counts once the emulation is over: as a JSON report,
+//! and as a heatmap of the 4 KiB of memory in a BMP image.
+//!
+//! The report lists the addresses accessed at least once, and the ranges of
+//! the ROM never accessed, which are either dead code or unused data:
+//!
+//! ```json
+//! {
+//!   "rom": "5b1a0b1b2ba2e1d1b5b4f1e7a1c8d3b8e3f2a1c4",
+//!   "instructions": 8104,
+//!   "rom_bytes": 478,
+//!   "unused": [
+//!     {"start": 918, "end": 923}
+//!   ],
+//!   "addresses": [
+//!     {"address": 512, "executed": 1, "read": 0, "written": 0},
+//!     ...
+//!   ]
+//! }
+//! ```
+
+use crate::cpu::{AccessKind, CPU, MEMORY_SIZE};
+use crate::monitor::Monitor;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+const START: usize = 0x200;
+
+// The heatmap has a square of CELL_SIZE pixels per address, ADDRESSES_PER_ROW
+// addresses per row
+const ADDRESSES_PER_ROW: usize = 64;
+const CELL_SIZE: usize = 8;
+const HEATMAP_WIDTH: usize = ADDRESSES_PER_ROW * CELL_SIZE;
+const HEATMAP_HEIGHT: usize = MEMORY_SIZE / ADDRESSES_PER_ROW * CELL_SIZE;
+
+// Bytes of the ROM never accessed, to tell them from the free memory
+const UNUSED_COLOR: u32 = 0x303030;
+// Intensity of the addresses accessed once, so that they stand out
+const MIN_INTENSITY: f64 = 0.25;
+
+pub struct Coverage {
+    report: Option<(String, File)>,
+    heatmap: Option<(String, File)>,
+    rom_size: usize,
+    executed: Vec<u64>,
+    read: Vec<u64>,
+    written: Vec<u64>,
+    instructions: u64,
+}
+
+impl Coverage {
+    /// Create the files of the report and the heatmap, saved by
+    /// [`Monitor::finish`].
+    pub fn create(
+        report: Option<&str>,
+        heatmap: Option<&str>,
+        rom_size: usize,
+    ) -> Result<Coverage, String> {
+        let create = |path: &str| {
+            File::create(path)
+                .map(|file| (path.to_string(), file))
+                .map_err(|error| format!("cannot create {}: {}", path, error))
+        };
+
+        Ok(Coverage {
+            report: report.map(create).transpose()?,
+            heatmap: heatmap.map(create).transpose()?,
+            rom_size: rom_size.min(MEMORY_SIZE - START),
+            executed: vec![0; MEMORY_SIZE],
+            read: vec![0; MEMORY_SIZE],
+            written: vec![0; MEMORY_SIZE],
+            instructions: 0,
+        })
+    }
+
+    fn is_accessed(&self, address: usize) -> bool {
+        self.executed[address] > 0 || self.read[address] > 0 || self.written[address] > 0
+    }
+
+    /// Ranges of the ROM never accessed, inclusive.
+    fn unused(&self) -> Vec<(usize, usize)> {
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+        for address in START..START + self.rom_size {
+            if self.is_accessed(address) {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some((_, end)) if *end + 1 == address => *end = address,
+                _ => ranges.push((address, address)),
+            }
+        }
+        ranges
+    }
+
+    fn write_report(&self, file: &mut impl Write, rom_hash: &str) -> std::io::Result<()> {
+        writeln!(file, "{{")?;
+        writeln!(file, "  \"rom\": \"{}\",", rom_hash)?;
+        writeln!(file, "  \"instructions\": {},", self.instructions)?;
+        writeln!(file, "  \"rom_bytes\": {},", self.rom_size)?;
+
+        let unused: Vec<String> = self
+            .unused()
+            .iter()
+            .map(|(start, end)| format!("    {{\"start\": {}, \"end\": {}}}", start, end))
+            .collect();
+        writeln!(file, "  \"unused\": [{}],", json_list(&unused))?;
+
+        let addresses: Vec<String> = (0..MEMORY_SIZE)
+            .filter(|address| self.is_accessed(*address))
+            .map(|address| {
+                format!(
+                    "    {{\"address\": {}, \"executed\": {}, \"read\": {}, \"written\": {}}}",
+                    address, self.executed[address], self.read[address], self.written[address]
+                )
+            })
+            .collect();
+        writeln!(file, "  \"addresses\": [{}]", json_list(&addresses))?;
+        writeln!(file, "}}")?;
+        file.flush()
+    }
+
+    /// Color of an address: executed in green, read in blue and written in
+    /// red, brighter the more it is accessed.
+    fn color(&self, address: usize, max: [u64; 3]) -> u32 {
+        let counts = [
+            self.written[address],
+            self.executed[address],
+            self.read[address],
+        ];
+        if counts == [0; 3] {
+            let in_rom = (START..START + self.rom_size).contains(&address);
+            return if in_rom { UNUSED_COLOR } else { 0 };
+        }
+
+        counts.iter().zip(max).fold(0, |color, (count, max)| {
+            let intensity = if *count == 0 {
+                0.0
+            } else {
+                // Logarithmic, the loops being executed much more than the
+                // rest of the program
+                let amount = (*count as f64).ln_1p() / (max as f64).ln_1p();
+                MIN_INTENSITY + (1.0 - MIN_INTENSITY) * amount
+            };
+            (color << 8) | (intensity * 255.0).round() as u32
+        })
+    }
+
+    fn write_heatmap(&self, file: &mut impl Write) -> std::io::Result<()> {
+        let max = [&self.written, &self.executed, &self.read]
+            .map(|counts| counts.iter().copied().max().unwrap_or(0));
+
+        let mut pixels = vec![0; HEATMAP_WIDTH * HEATMAP_HEIGHT];
+        for address in 0..MEMORY_SIZE {
+            let color = self.color(address, max);
+            let x = address % ADDRESSES_PER_ROW * CELL_SIZE;
+            let y = address / ADDRESSES_PER_ROW * CELL_SIZE;
+            for row in y..y + CELL_SIZE {
+                pixels[row * HEATMAP_WIDTH + x..][..CELL_SIZE].fill(color);
+            }
+        }
+        write_bmp(file, HEATMAP_WIDTH, HEATMAP_HEIGHT, &pixels)?;
+        file.flush()
+    }
+}
+
+/// JSON array items, one per line.
+fn json_list(items: &[String]) -> String {
+    if items.is_empty() {
+        String::new()
+    } else {
+        format!("\n{}\n  ", items.join(",\n"))
+    }
+}
+
+/// Write a 24 bits uncompressed BMP image of `0xRRGGBB` pixels.
+fn write_bmp(
+    file: &mut impl Write,
+    width: usize,
+    height: usize,
+    pixels: &[u32],
+) -> std::io::Result<()> {
+    // Rows are padded to 4 bytes
+    let row_size = (width * 3).div_ceil(4) * 4;
+    let data_size = row_size * height;
+    let header_size = 14 + 40;
+
+    // File header
+    file.write_all(b"BM")?;
+    file.write_all(&((header_size + data_size) as u32).to_le_bytes())?;
+    file.write_all(&[0; 4])?;
+    file.write_all(&(header_size as u32).to_le_bytes())?;
+
+    // Info header
+    file.write_all(&40u32.to_le_bytes())?;
+    file.write_all(&(width as i32).to_le_bytes())?;
+    file.write_all(&(height as i32).to_le_bytes())?;
+    file.write_all(&1u16.to_le_bytes())?; // Planes
+    file.write_all(&24u16.to_le_bytes())?; // Bits per pixel
+    file.write_all(&0u32.to_le_bytes())?; // No compression
+    file.write_all(&(data_size as u32).to_le_bytes())?;
+    file.write_all(&2835i32.to_le_bytes())?; // 72 DPI
+    file.write_all(&2835i32.to_le_bytes())?;
+    file.write_all(&[0; 8])?; // No palette
+
+    // Rows from the bottom, pixels in blue, green, red order
+    let mut row = vec![0; row_size];
+    for y in (0..height).rev() {
+        for (x, pixel) in pixels[y * width..][..width].iter().enumerate() {
+            row[x * 3..x * 3 + 3].copy_from_slice(&pixel.to_le_bytes()[..3]);
+        }
+        file.write_all(&row)?;
+    }
+    Ok(())
+}
+
+impl Monitor for Coverage {
+    fn after_instruction(&mut self, cpu: &CPU) {
+        for access in &cpu.accesses {
+            let address = access.address as usize % MEMORY_SIZE;
+            match access.kind {
+                AccessKind::Execute => self.executed[address] += 1,
+                AccessKind::Read => self.read[address] += 1,
+                AccessKind::Write => self.written[address] += 1,
+            }
+        }
+        self.instructions += 1;
+    }
+
+    fn finish(&mut self, cpu: &CPU) {
+        if let Some((path, file)) = self.report.take() {
+            if let Err(error) = self.write_report(&mut BufWriter::new(file), &cpu.rom_hash) {
+                println!("cannot write {}: {}", path, error);
+            }
+        }
+        if let Some((path, file)) = self.heatmap.take() {
+            if let Err(error) = self.write_heatmap(&mut BufWriter::new(file)) {
+                println!("cannot write {}: {}", path, error);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixel::Screen;
+
+    // Reads a table of two bytes then loops, with dead code before the table
+    // and an unused byte after it
+    const ROM: [u8; 13] = [
+        0xA2, 0x0A, // 200: LD I, #20A
+        0xF1, 0x65, // 202: LD V1, [I]
+        0x12, 0x04, // 204: JP #204
+        0x00, 0xE0, // 206: CLS
+        0x12, 0x06, // 208: JP #206
+        0x12, 0x34, // 20A: table
+        0x56, // 20C: unused
+    ];
+
+    fn run(instructions: usize) -> Coverage {
+        let mut coverage = Coverage::create(None, None, ROM.len()).unwrap();
+        let mut cpu = CPU::new();
+        let mut screen = Screen::new();
+        cpu.memory[START..START + ROM.len()].copy_from_slice(&ROM);
+
+        for _ in 0..instructions {
+            cpu.execute_opcode(cpu.get_opcode(), &mut screen);
+            coverage.after_instruction(&cpu);
+        }
+        coverage
+    }
+
+    #[test]
+    fn tells_instructions_from_data() {
+        let coverage = run(5);
+
+        assert_eq!(
+            coverage.executed[0x200..0x20D],
+            [1, 1, 1, 1, 3, 3, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            coverage.read[0x200..0x20D],
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 0]
+        );
+        assert_eq!(coverage.written, vec![0; MEMORY_SIZE]);
+        assert_eq!(coverage.unused(), [(0x206, 0x209), (0x20C, 0x20C)]);
+    }
+
+    #[test]
+    fn reports_the_unused_ranges() {
+        let mut report = Vec::new();
+        run(3).write_report(&mut report, "abc").unwrap();
+
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            r#"{
+  "rom": "abc",
+  "instructions": 3,
+  "rom_bytes": 13,
+  "unused": [
+    {"start": 518, "end": 521},
+    {"start": 524, "end": 524}
+  ],
+  "addresses": [
+    {"address": 512, "executed": 1, "read": 0, "written": 0},
+    {"address": 513, "executed": 1, "read": 0, "written": 0},
+    {"address": 514, "executed": 1, "read": 0, "written": 0},
+    {"address": 515, "executed": 1, "read": 0, "written": 0},
+    {"address": 516, "executed": 1, "read": 0, "written": 0},
+    {"address": 517, "executed": 1, "read": 0, "written": 0},
+    {"address": 522, "executed": 0, "read": 1, "written": 0},
+    {"address": 523, "executed": 0, "read": 1, "written": 0}
+  ]
+}
+"#
+        );
+    }
+}
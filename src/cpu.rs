@@ -4,27 +4,81 @@
 //! The CPU struct contains the registers, memory, stack, and other
 //! components needed to emulate the Chip8 CPU.
 
-use crate::pixel::{Pixel, PixelDisplay};
+use crate::pixel::{Pixel, Screen};
+use crate::quirks::Quirks;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const NUMBER_OPCODES: usize = 44;
+
+/// Size of the Chip8 memory.
+pub const MEMORY_SIZE: usize = 0x1000;
+
+/// Address of the 8x10 digits of the SUPER-CHIP, after the 4x5 ones.
+pub const BIG_FONT_START: u16 = 0x50;
+
+// 10 bytes per digit from 0 to F
+const BIG_FONT: [u8; 160] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x3C, 0x7E, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFE, 0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC, // B
+    0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0x7E, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+/// How the program accesses a byte of memory.
+#[derive(Clone, Copy, PartialEq)]
+pub enum AccessKind {
+    /// Opcode fetch.
+    Execute,
+    Read,
+    Write,
+}
 
-const NUMBER_OPCODES: usize = 35;
+#[derive(Clone, Copy)]
+pub struct Access {
+    pub address: u16,
+    pub kind: AccessKind,
+}
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct CPU {
-    pub memory: [u8; 4096],
-    pub v: [u8; 16],       // Registers
-    pub i: u16,            // Index register
-    pub jump: [u16; 16],   // Jump address
-    pub number_jump: u8,   // Number of jumps
-    pub game_counter: u8,  // Game counter
-    pub sound_counter: u8, // Sound counter
-    pub pc: u16,           // Program counter
-    pub key: [bool; 16],   // Key
-    pub jp: Jump,          // Jump
+    pub memory: [u8; MEMORY_SIZE],
+    pub v: [u8; 16],              // Registers
+    pub i: u16,                   // Index register
+    pub jump: [u16; 16],          // Jump address
+    pub number_jump: u8,          // Number of jumps
+    pub game_counter: u8,         // Game counter
+    pub sound_counter: u8,        // Sound counter
+    pub pc: u16,                  // Program counter
+    pub key: [bool; 16],          // Key
+    pub key_pressed: [bool; 16],  // Keys pressed since the last update
+    pub key_released: [bool; 16], // Keys released since the last update
+    pub key_polled: [bool; 16],   // Keys read by EX9E and EXA1 since the last update
+    pub jp: Jump,                 // Jump
+    pub quirks: Quirks,           // Interpreter behaviours
+    pub waiting_vblank: bool,
+    pub rng: StdRng,           // Random numbers of CXNN
+    pub rom_hash: String,      // SHA-1 of the loaded ROM
+    pub accesses: Vec<Access>, // Memory accessed by the last instruction
+    pub flags: [u8; 16],       // Registers saved by FX75
 }
 
 impl CPU {
     pub fn new() -> CPU {
         let mut cpu = CPU {
-            memory: [0; 4096],
+            memory: [0; MEMORY_SIZE],
             v: [0; 16],
             i: 0,
             jump: [0; 16],
@@ -33,7 +87,16 @@ impl CPU {
             sound_counter: 0,
             pc: 0x200,
             key: [false; 16],
+            key_pressed: [false; 16],
+            key_released: [false; 16],
+            key_polled: [false; 16],
             jp: Jump::new(),
+            quirks: Quirks::new(),
+            waiting_vblank: false,
+            rng: StdRng::from_entropy(),
+            rom_hash: String::new(),
+            accesses: Vec::new(),
+            flags: [0; 16],
         };
 
         // Load font
@@ -42,7 +105,26 @@ impl CPU {
         cpu
     }
 
+    /// Make the random numbers reproducible.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Update the whole keypad at once, keeping track of the keys pressed and
+    /// released since the previous update.
+    pub fn set_keys(&mut self, keys: [bool; 16]) {
+        for (index, down) in keys.iter().enumerate() {
+            self.key_pressed[index] = *down && !self.key[index];
+            self.key_released[index] = !*down && self.key[index];
+        }
+        self.key = keys;
+        self.key_polled = [false; 16];
+    }
+
+    /// Called on every vertical blank, 60 times per second.
     pub fn count_down(&mut self) {
+        self.waiting_vblank = false;
+
         if self.game_counter > 0 {
             self.game_counter -= 1;
         }
@@ -53,11 +135,10 @@ impl CPU {
 
     pub fn load_rom(&mut self, rom: &str) -> bool {
         let rom = std::fs::read(rom).unwrap();
-        let mut i = 0x200;
+        self.rom_hash = sha1_smol::Sha1::from(&rom).digest().to_string();
 
-        for byte in rom.iter() {
-            self.memory[i] = *byte as u8;
-            i += 1;
+        for (i, byte) in rom.iter().enumerate() {
+            self.memory[0x200 + i] = *byte;
         }
 
         true
@@ -175,20 +256,53 @@ impl CPU {
         self.memory[77] = 0xF0;
         self.memory[78] = 0x80;
         self.memory[79] = 0x80;
+
+        // Big digits of the SUPER-CHIP
+        let start = BIG_FONT_START as usize;
+        self.memory[start..start + BIG_FONT.len()].copy_from_slice(&BIG_FONT);
     }
 
+    /// Opcode at the program counter. Like the other accesses, addresses past
+    /// the end of the memory wrap around to its start.
     pub fn get_opcode(&self) -> u16 {
-        ((self.memory[self.pc as usize] as u16) << 8) + self.memory[(self.pc + 1) as usize] as u16
+        let (first, second) = (self.pc & 0xFFF, (self.pc + 1) & 0xFFF);
+        ((self.memory[first as usize] as u16) << 8) + self.memory[second as usize] as u16
+    }
+
+    /// Read a byte of memory for the program, recording the access. Addresses
+    /// past the end of the memory wrap around to its start.
+    pub fn read_memory(&mut self, address: u16) -> u8 {
+        let address = address & 0xFFF;
+        self.accesses.push(Access {
+            address,
+            kind: AccessKind::Read,
+        });
+        self.memory[address as usize]
+    }
+
+    /// Write a byte of memory for the program, recording the access. Addresses
+    /// past the end of the memory wrap around to its start.
+    pub fn write_memory(&mut self, address: u16, value: u8) {
+        let address = address & 0xFFF;
+        self.accesses.push(Access {
+            address,
+            kind: AccessKind::Write,
+        });
+        self.memory[address as usize] = value;
     }
 
-    pub fn execute_opcode(
-        &mut self,
-        opcode: u16,
-        screen: &mut PixelDisplay,
-        pixels: &mut Vec<Pixel>,
-    ) {
+    /// Execute the opcode at the program counter.
+    pub fn execute_opcode(&mut self, opcode: u16, screen: &mut Screen) {
         let action = self.jp.get_action(opcode);
 
+        self.accesses.clear();
+        for address in [self.pc & 0xFFF, (self.pc + 1) & 0xFFF] {
+            self.accesses.push(Access {
+                address,
+                kind: AccessKind::Execute,
+            });
+        }
+
         let b3 = (opcode & 0x0F00) >> 8;
         let b2 = (opcode & 0x00F0) >> 4;
         let b1 = opcode & 0x000F;
@@ -196,8 +310,7 @@ impl CPU {
         match action {
             1 => {
                 // 00E0 : clear the screen
-                Pixel::clear_screen(pixels);
-                screen.draw(pixels);
+                Pixel::clear_screen(screen);
             }
             2 => {
                 // 00EE : return from a subroutine
@@ -313,39 +426,54 @@ impl CPU {
             }
             22 => {
                 // CXNN : set V[X] = random byte AND NN
-                self.v[b3 as usize] = ((rand::random::<u16>()) % ((b2 << 4) + b1 + 1)) as u8;
+                self.v[b3 as usize] = (self.rng.gen::<u16>() % ((b2 << 4) + b1 + 1)) as u8;
             }
             23 => {
                 // DXYN : draw a sprite at position V[X], V[Y] with N bytes of sprite data
-                Pixel::draw_screen(self, pixels, b1, b2, b3);
+                Pixel::draw_screen(self, screen, b1, b2, b3);
+
+                if self.quirks.display_wait {
+                    // Stall until the next frame, see count_down
+                    self.waiting_vblank = true;
+                }
             }
             24 => {
                 // EX9E : skip next instruction if key with the value of V[X] is pressed
+                self.key_polled[self.v[b3 as usize] as usize] = true;
                 if self.key[self.v[b3 as usize] as usize] {
                     self.pc += 2;
                 }
             }
             25 => {
                 // EXA1 : skip next instruction if key with the value of V[X] is not pressed
+                self.key_polled[self.v[b3 as usize] as usize] = true;
                 if !self.key[self.v[b3 as usize] as usize] {
                     self.pc += 2;
                 }
             }
             26 => {
                 // FX07 : set V[X] = delay timer value
-                self.v[b3 as usize] = self.game_counter as u8;
+                self.v[b3 as usize] = self.game_counter;
             }
             27 => {
                 // FX0A : wait for a key press, store the value of the key in V[X]
-                screen.wait_key_pressed(self);
+                // Like the COSMAC VIP, the key is only taken once released
+                match self.key_released.iter().position(|released| *released) {
+                    Some(key) => {
+                        self.v[b3 as usize] = key as u8;
+                        self.key_released[key] = false;
+                    }
+                    // Execute the instruction again until a key is released
+                    None => self.pc -= 2,
+                }
             }
             28 => {
                 // FX15 : set delay timer = V[X]
-                self.game_counter = self.v[b3 as usize] as u8;
+                self.game_counter = self.v[b3 as usize];
             }
             29 => {
                 // FX18 : set sound timer = V[X]
-                self.sound_counter = self.v[b3 as usize] as u8;
+                self.sound_counter = self.v[b3 as usize];
             }
             30 => {
                 // FX1E : set I = I + V[X]
@@ -359,39 +487,65 @@ impl CPU {
             }
             32 => {
                 // FX33 : store BCD representation of V[X] in memory locations I, I+1, and I+2
+                let value = self.v[b3 as usize];
 
-                self.memory[self.i as usize] = self.v[b3 as usize]
-                    .checked_sub(self.v[b3 as usize] % 100)
-                    .unwrap_or_default()
-                    / 100;
-
-                self.memory[(self.i + 1) as usize] = ((self.v[b3 as usize]
-                    .checked_sub(self.v[b3 as usize] % 10)
-                    .unwrap_or_default()
-                    / 10)
-                    % 10) as u8;
-
-                self.memory[(self.i + 2) as usize] = (self.v[b3 as usize]
-                    .checked_sub(
-                        self.memory[self.i as usize]
-                            .checked_mul(100)
-                            .unwrap_or_default(),
-                    )
-                    .unwrap_or_default()
-                    .checked_sub(10 * self.memory[(self.i + 1) as usize])
-                    .unwrap_or_default())
-                    as u8;
+                self.write_memory(self.i, value / 100);
+                self.write_memory(self.i.wrapping_add(1), (value / 10) % 10);
+                self.write_memory(self.i.wrapping_add(2), value % 10);
             }
             33 => {
                 // FX55 : store registers V[0] through V[X] in memory starting at location I
                 for j in 0..=b3 {
-                    self.memory[(self.i + j) as usize] = self.v[j as usize] as u8;
+                    self.write_memory(self.i.wrapping_add(j), self.v[j as usize]);
                 }
             }
             34 => {
                 // FX65 : read registers V[0] through V[X] from memory starting at location I
                 for j in 0..=b3 {
-                    self.v[j as usize] = self.memory[(self.i + j) as usize] as u8;
+                    self.v[j as usize] = self.read_memory(self.i.wrapping_add(j));
+                }
+            }
+            35 => {
+                // 00CN : scroll the screen down N pixels
+                screen.scroll(0, b1 as i32);
+            }
+            36 => {
+                // 00FB : scroll the screen right 4 pixels
+                screen.scroll(4, 0);
+            }
+            37 => {
+                // 00FC : scroll the screen left 4 pixels
+                screen.scroll(-4, 0);
+            }
+            38 => {
+                // 00FD : exit the interpreter
+                // Like FX0A, the instruction is executed again, without
+                // spinning through the whole frame
+                self.pc -= 2;
+                self.waiting_vblank = true;
+            }
+            39 => {
+                // 00FE : switch to the low resolution
+                screen.set_hires(false);
+            }
+            40 => {
+                // 00FF : switch to the high resolution
+                screen.set_hires(true);
+            }
+            41 => {
+                // FX30 : set I = location of the big sprite for digit V[X]
+                self.i = BIG_FONT_START + (self.v[b3 as usize] & 0xF) as u16 * 10;
+            }
+            42 => {
+                // FX75 : save registers V[0] through V[X] in the flags
+                for j in 0..=b3 as usize {
+                    self.flags[j] = self.v[j];
+                }
+            }
+            43 => {
+                // FX85 : load registers V[0] through V[X] from the flags
+                for j in 0..=b3 as usize {
+                    self.v[j] = self.flags[j];
                 }
             }
             _ => println!("Unknown opcode: {:X}", opcode),
@@ -401,6 +555,12 @@ impl CPU {
     }
 }
 
+impl Default for CPU {
+    fn default() -> CPU {
+        CPU::new()
+    }
+}
+
 pub struct Jump {
     pub mask: [u16; NUMBER_OPCODES],
     pub id: [u16; NUMBER_OPCODES],
@@ -553,6 +713,42 @@ impl Jump {
         jp.mask[34] = 0xF0FF;
         jp.id[34] = 0xF065;
 
+        // 00CN
+        jp.mask[35] = 0xFFF0;
+        jp.id[35] = 0x00C0;
+
+        // 00FB
+        jp.mask[36] = 0xFFFF;
+        jp.id[36] = 0x00FB;
+
+        // 00FC
+        jp.mask[37] = 0xFFFF;
+        jp.id[37] = 0x00FC;
+
+        // 00FD
+        jp.mask[38] = 0xFFFF;
+        jp.id[38] = 0x00FD;
+
+        // 00FE
+        jp.mask[39] = 0xFFFF;
+        jp.id[39] = 0x00FE;
+
+        // 00FF
+        jp.mask[40] = 0xFFFF;
+        jp.id[40] = 0x00FF;
+
+        // FX30
+        jp.mask[41] = 0xF0FF;
+        jp.id[41] = 0xF030;
+
+        // FX75
+        jp.mask[42] = 0xF0FF;
+        jp.id[42] = 0xF075;
+
+        // FX85
+        jp.mask[43] = 0xF0FF;
+        jp.id[43] = 0xF085;
+
         jp
     }
 
@@ -568,3 +764,105 @@ impl Jump {
         0
     }
 }
+
+impl Default for Jump {
+    fn default() -> Jump {
+        Jump::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(down: &[usize]) -> [bool; 16] {
+        std::array::from_fn(|key| down.contains(&key))
+    }
+
+    #[test]
+    fn tracks_pressed_and_released_keys() {
+        let mut cpu = CPU::new();
+
+        cpu.set_keys(keys(&[1, 2]));
+        assert_eq!(cpu.key, keys(&[1, 2]));
+        assert_eq!(cpu.key_pressed, keys(&[1, 2]));
+        assert_eq!(cpu.key_released, keys(&[]));
+
+        cpu.set_keys(keys(&[2, 3]));
+        assert_eq!(cpu.key_pressed, keys(&[3]));
+        assert_eq!(cpu.key_released, keys(&[1]));
+
+        cpu.set_keys(keys(&[2, 3]));
+        assert_eq!(cpu.key_pressed, keys(&[]));
+        assert_eq!(cpu.key_released, keys(&[]));
+    }
+
+    #[test]
+    fn waits_for_a_key_release() {
+        let mut cpu = CPU::new();
+        let mut screen = Screen::new();
+
+        // FX0A is executed again while no key is released
+        cpu.set_keys(keys(&[7]));
+        cpu.execute_opcode(0xF50A, &mut screen);
+        assert_eq!(cpu.pc, 0x200);
+
+        cpu.set_keys(keys(&[]));
+        cpu.execute_opcode(0xF50A, &mut screen);
+        assert_eq!((cpu.pc, cpu.v[5]), (0x202, 7));
+
+        // A release is only taken once
+        cpu.pc = 0x200;
+        cpu.execute_opcode(0xF50A, &mut screen);
+        assert_eq!(cpu.pc, 0x200);
+    }
+
+    #[test]
+    fn runs_the_super_chip_instructions() {
+        let mut cpu = CPU::new();
+        let mut screen = Screen::new();
+
+        cpu.execute_opcode(0x00FF, &mut screen);
+        assert_eq!(screen.width(), 128);
+        cpu.execute_opcode(0x00FE, &mut screen);
+        assert_eq!(screen.width(), 64);
+
+        // The big digits are 10 bytes each
+        cpu.v[3] = 0xA;
+        cpu.execute_opcode(0xF330, &mut screen);
+        assert_eq!(cpu.i, BIG_FONT_START + 100);
+        assert_eq!(cpu.memory[cpu.i as usize..][..2], [0x3C, 0x7E]);
+
+        cpu.v[..3].copy_from_slice(&[1, 2, 3]);
+        cpu.execute_opcode(0xF175, &mut screen);
+        cpu.v = [0; 16];
+        cpu.execute_opcode(0xF285, &mut screen);
+        assert_eq!(cpu.v[..3], [1, 2, 0]);
+
+        // Exit stops on itself until the next frame
+        let pc = cpu.pc;
+        cpu.execute_opcode(0x00FD, &mut screen);
+        assert_eq!(cpu.pc, pc);
+        assert!(cpu.waiting_vblank);
+    }
+
+    #[test]
+    fn wraps_the_opcode_at_the_end_of_the_memory() {
+        let mut cpu = CPU::new();
+        let mut screen = Screen::new();
+        cpu.pc = 0xFFF;
+        cpu.memory[0xFFF] = 0x60;
+
+        // The second byte is the first byte of the font
+        let opcode = cpu.get_opcode();
+        assert_eq!(opcode, 0x60F0);
+        cpu.execute_opcode(opcode, &mut screen);
+
+        let executed: Vec<u16> = cpu.accesses.iter().map(|access| access.address).collect();
+        assert_eq!(executed, [0xFFF, 0x000]);
+        assert!(cpu
+            .accesses
+            .iter()
+            .all(|access| access.kind == AccessKind::Execute));
+    }
+}
@@ -0,0 +1,196 @@
+//! # CRT
+//!
+//! Software post-processing imitating a CRT monitor: scanlines, horizontal
+//! bloom, curvature, vignette and RGB mask. It is applied by the CPU to the
+//! scaled window buffer right before it is presented.
+
+// Distance in window pixels the bloom spreads light horizontally
+const BLOOM_RADIUS: usize = 3;
+
+// Barrel distortion applied at full curvature strength
+const MAX_CURVATURE: f32 = 0.12;
+
+pub struct Crt {
+    /// Darkening of every other line.
+    scanlines: f32,
+    /// Light bleeding horizontally around lit pixels.
+    bloom: f32,
+    /// Bending of the picture like the glass of a tube.
+    curvature: f32,
+    /// Darkening of the corners.
+    vignette: f32,
+    /// Red, green and blue phosphor stripes.
+    mask: f32,
+    width: usize,
+    height: usize,
+    // Caches depending only on the buffer size, computed on resize
+    samples: Vec<Option<usize>>,
+    gains: Vec<[f32; 3]>,
+    bloomed: Vec<[f32; 3]>,
+    output: Vec<u32>,
+}
+
+impl Crt {
+    fn new(scanlines: f32, bloom: f32, curvature: f32, vignette: f32, mask: f32) -> Crt {
+        Crt {
+            scanlines,
+            bloom,
+            curvature,
+            vignette,
+            mask,
+            width: 0,
+            height: 0,
+            samples: Vec::new(),
+            gains: Vec::new(),
+            bloomed: Vec::new(),
+            output: Vec::new(),
+        }
+    }
+
+    pub fn preset(name: &str) -> Option<Crt> {
+        match name {
+            "subtle" => Some(Crt::new(0.25, 0.15, 0.0, 0.15, 0.1)),
+            "classic" => Some(Crt::new(0.45, 0.3, 0.5, 0.3, 0.25)),
+            "heavy" => Some(Crt::new(0.7, 0.5, 1.0, 0.5, 0.5)),
+            _ => None,
+        }
+    }
+
+    /// Parse a preset name optionally followed by effect strengths between 0
+    /// and 1 overriding the preset, e.g. `classic,curvature=0,bloom=0.6`.
+    pub fn parse(spec: &str) -> Result<Crt, String> {
+        let mut parts = spec.split(',');
+        let name = parts.next().unwrap_or_default();
+        let mut crt = Crt::preset(name).ok_or(format!("unknown crt preset: {}", name))?;
+
+        for part in parts {
+            let (effect, value) = part
+                .split_once('=')
+                .ok_or(format!("expected effect=strength: {}", part))?;
+            let value = match value.parse::<f32>() {
+                Ok(value) if (0.0..=1.0).contains(&value) => value,
+                _ => return Err(format!("invalid strength for {}: {}", effect, value)),
+            };
+
+            match effect {
+                "scanlines" => crt.scanlines = value,
+                "bloom" => crt.bloom = value,
+                "curvature" => crt.curvature = value,
+                "vignette" => crt.vignette = value,
+                "mask" => crt.mask = value,
+                _ => return Err(format!("unknown crt effect: {}", effect)),
+            }
+        }
+
+        Ok(crt)
+    }
+
+    /// Post-process a `width` x `height` buffer into [`Crt::output`].
+    pub fn apply(&mut self, buffer: &[u32], width: usize, height: usize) {
+        if width != self.width || height != self.height {
+            self.resize(width, height);
+        }
+
+        // Bloom: add a horizontal blur of the row to itself
+        for y in 0..height {
+            let row = &buffer[y * width..(y + 1) * width];
+            for x in 0..width {
+                let mut color = channels(row[x]);
+
+                if self.bloom > 0.0 {
+                    let start = x.saturating_sub(BLOOM_RADIUS);
+                    let end = (x + BLOOM_RADIUS + 1).min(width);
+                    let mut blur = [0.0; 3];
+                    for pixel in &row[start..end] {
+                        let neighbour = channels(*pixel);
+                        for c in 0..3 {
+                            blur[c] += neighbour[c];
+                        }
+                    }
+                    for c in 0..3 {
+                        color[c] += self.bloom * blur[c] / (end - start) as f32;
+                    }
+                }
+
+                self.bloomed[y * width + x] = color;
+            }
+        }
+
+        // Curvature, then scanlines, vignette and mask
+        for (index, output) in self.output.iter_mut().enumerate() {
+            *output = match self.samples[index] {
+                Some(sample) => {
+                    let color = self.bloomed[sample];
+                    let gain = self.gains[index];
+                    pack([color[0] * gain[0], color[1] * gain[1], color[2] * gain[2]])
+                }
+                None => 0,
+            };
+        }
+    }
+
+    /// The last buffer filtered by [`Crt::apply`].
+    pub fn output(&self) -> &[u32] {
+        &self.output
+    }
+
+    fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.samples = Vec::with_capacity(width * height);
+        self.gains = Vec::with_capacity(width * height);
+        self.bloomed = vec![[0.0; 3]; width * height];
+        self.output = vec![0; width * height];
+
+        let curvature = self.curvature * MAX_CURVATURE;
+
+        for y in 0..height {
+            for x in 0..width {
+                // Coordinates of the pixel center between -1 and 1
+                let u = (x as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+                let v = (y as f32 + 0.5) / height as f32 * 2.0 - 1.0;
+
+                let source_u = u * (1.0 + curvature * v * v);
+                let source_v = v * (1.0 + curvature * u * u);
+                let source_x = ((source_u + 1.0) / 2.0 * width as f32).floor();
+                let source_y = ((source_v + 1.0) / 2.0 * height as f32).floor();
+                let inside = source_x >= 0.0
+                    && source_y >= 0.0
+                    && source_x < width as f32
+                    && source_y < height as f32;
+                self.samples.push(if inside {
+                    Some(source_y as usize * width + source_x as usize)
+                } else {
+                    None
+                });
+
+                let scanline = if y % 2 == 1 {
+                    1.0 - self.scanlines
+                } else {
+                    1.0
+                };
+                let vignette = 1.0 - self.vignette * (u * u + v * v) / 2.0;
+                let mut gain = [scanline * vignette; 3];
+                for (c, channel) in gain.iter_mut().enumerate() {
+                    if x % 3 != c {
+                        *channel *= 1.0 - self.mask;
+                    }
+                }
+                self.gains.push(gain);
+            }
+        }
+    }
+}
+
+fn channels(color: u32) -> [f32; 3] {
+    [
+        ((color >> 16) & 0xFF) as f32,
+        ((color >> 8) & 0xFF) as f32,
+        (color & 0xFF) as f32,
+    ]
+}
+
+fn pack(color: [f32; 3]) -> u32 {
+    let channel = |value: f32| value.clamp(0.0, 255.0) as u32;
+    (channel(color[0]) << 16) | (channel(color[1]) << 8) | channel(color[2])
+}
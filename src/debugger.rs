@@ -0,0 +1,473 @@
+//! # Debugger
+//!
+//! Command line debugger reading commands from the standard input while the
+//! emulator keeps running and drawing its frames. Addresses and values are
+//! hexadecimal, counts are decimal. Addresses can also be given by the names
+//! of the symbol file, which name the addresses shown.
+
+use crate::cpu::{AccessKind, CPU};
+use crate::monitor::{Control, Monitor, Stop, Watchpoint};
+use crate::symbols::Symbols;
+use std::io::Write;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+use std::time::Duration;
+
+const HELP: &str = "Commands:
+    s, step [n]             execute n instructions (default: 1)
+    c, continue             run until a breakpoint
+    stop                    stop the program
+    b, break [addr]         add a breakpoint, or list them
+    delete <addr>           remove a breakpoint
+    w, watch [rwx] <addr> [len]
+                            stop after the program reads, writes or
+                            executes len bytes (default: 1, write), or list
+                            the watchpoints
+    unwatch <addr>          remove the watchpoints starting at addr
+    r, regs                 show the registers and timers
+    set <reg> <value>       set v0-vf, i, pc, dt or st
+    m, mem <addr> [len]     dump memory (default: 64 bytes)
+    poke <addr> <byte>...   write bytes to memory
+    stack                   show the call stack
+    d, dis [addr] [n]       disassemble n instructions (default: 10 from pc)
+    h, help                 show this help
+
+Addresses are hexadecimal, or symbols with an optional hexadecimal offset,
+e.g. `break main_loop+4`.";
+
+// How long to wait for a command at every frame while the program is stopped,
+// instead of spinning when frames are not paced by a display
+const COMMAND_WAIT: Duration = Duration::from_millis(16);
+
+// Instructions in the whole memory, the most `dis` shows
+const MAX_DISASSEMBLY: u32 = 0x800;
+
+pub struct Debugger {
+    commands: Receiver<String>,
+    control: Control,
+    symbols: Symbols,
+}
+
+impl Debugger {
+    /// Start reading commands, the program being stopped on its first
+    /// instruction.
+    pub fn new(cpu: &CPU, symbols: Symbols) -> Debugger {
+        let (sender, commands) = mpsc::channel();
+
+        // Reading the standard input blocks, so wait for commands in another
+        // thread and receive them at every frame
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        println!("Debugger started, type `help` for the commands");
+        show_instruction(cpu, &symbols, cpu.pc);
+        prompt();
+
+        Debugger {
+            commands,
+            control: Control::new(),
+            symbols,
+        }
+    }
+
+    fn run(&mut self, cpu: &mut CPU, command: &str) {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        let Some((name, args)) = words.split_first() else {
+            return;
+        };
+
+        let result = match *name {
+            "s" | "step" => self.step(cpu, args),
+            "c" | "continue" => {
+                self.control.resume();
+                Ok(())
+            }
+            "stop" => {
+                self.control.stop();
+                show_instruction(cpu, &self.symbols, cpu.pc);
+                Ok(())
+            }
+            "b" | "break" => self.add_breakpoint(args),
+            "delete" => self.delete_breakpoint(args),
+            "w" | "watch" => self.add_watchpoint(args),
+            "unwatch" => self.delete_watchpoint(args),
+            "r" | "regs" => {
+                show_registers(cpu);
+                Ok(())
+            }
+            "set" => set_register(cpu, &self.symbols, args),
+            "m" | "mem" => dump_memory(cpu, &self.symbols, args),
+            "poke" => poke(cpu, &self.symbols, args),
+            "stack" => {
+                show_stack(cpu, &self.symbols);
+                Ok(())
+            }
+            "d" | "dis" => disassemble(cpu, &self.symbols, args),
+            "h" | "help" => {
+                println!("{}", HELP);
+                Ok(())
+            }
+            _ => Err(format!("unknown command: {}", name)),
+        };
+
+        if let Err(error) = result {
+            println!("{}", error);
+        }
+    }
+
+    fn step(&mut self, cpu: &CPU, args: &[&str]) -> Result<(), String> {
+        let steps = match args.first() {
+            Some(count) => parse_count(count)?,
+            None => 1,
+        };
+
+        if steps > 0 {
+            self.control.step(steps);
+        } else {
+            show_instruction(cpu, &self.symbols, cpu.pc);
+        }
+        Ok(())
+    }
+
+    fn add_breakpoint(&mut self, args: &[&str]) -> Result<(), String> {
+        let Some(address) = args.first() else {
+            for address in &self.control.breakpoints {
+                println!("{}", self.symbols.describe(*address));
+            }
+            return Ok(());
+        };
+
+        let address = parse_address(address, &self.symbols)?;
+        if !self.control.breakpoints.contains(&address) {
+            self.control.breakpoints.push(address);
+        }
+        println!("Breakpoint at {}", self.symbols.describe(address));
+        Ok(())
+    }
+
+    fn delete_breakpoint(&mut self, args: &[&str]) -> Result<(), String> {
+        let address = parse_address(
+            args.first().ok_or("delete expects an address")?,
+            &self.symbols,
+        )?;
+        self.control
+            .breakpoints
+            .retain(|breakpoint| *breakpoint != address);
+        Ok(())
+    }
+
+    fn add_watchpoint(&mut self, args: &[&str]) -> Result<(), String> {
+        if args.is_empty() {
+            for watchpoint in &self.control.watchpoints {
+                println!("{}", describe_watchpoint(watchpoint, &self.symbols));
+            }
+            return Ok(());
+        }
+
+        // The kinds of access are optional, and cannot be taken for an
+        // address as they are not hexadecimal digits, but hide the symbols
+        // with the same name
+        let (kinds, args) = match args.split_first() {
+            Some((kinds, args)) if kinds.chars().all(|kind| "rwx".contains(kind)) => (*kinds, args),
+            _ => ("w", args),
+        };
+        let start = parse_address(
+            args.first().ok_or("watch expects an address")?,
+            &self.symbols,
+        )?;
+        let length = match args.get(1) {
+            Some(length) => parse_count(length)?,
+            None => 1,
+        };
+        if length == 0 || start as u32 + length > 0x1000 {
+            return Err(format!("invalid length: {}", length));
+        }
+
+        let watchpoint = Watchpoint {
+            start,
+            length: length as u16,
+            read: kinds.contains('r'),
+            write: kinds.contains('w'),
+            execute: kinds.contains('x'),
+        };
+        if !self.control.watchpoints.contains(&watchpoint) {
+            self.control.watchpoints.push(watchpoint);
+        }
+        println!(
+            "Watchpoint {}",
+            describe_watchpoint(&watchpoint, &self.symbols)
+        );
+        Ok(())
+    }
+
+    fn delete_watchpoint(&mut self, args: &[&str]) -> Result<(), String> {
+        let address = parse_address(
+            args.first().ok_or("unwatch expects an address")?,
+            &self.symbols,
+        )?;
+        self.control
+            .watchpoints
+            .retain(|watchpoint| watchpoint.start != address);
+        Ok(())
+    }
+
+    /// Show where and why the program stopped, if it did.
+    fn report_stop(&mut self, cpu: &CPU) {
+        match self.control.take_stop() {
+            Some(Stop::Breakpoint) => {
+                println!("Breakpoint at {}", self.symbols.describe(cpu.pc));
+                show_instruction(cpu, &self.symbols, cpu.pc);
+            }
+            Some(Stop::Watchpoint(watchpoint, access)) => {
+                let verb = match access.kind {
+                    AccessKind::Execute => "executed",
+                    AccessKind::Read => "read",
+                    AccessKind::Write => "written",
+                };
+                println!(
+                    "Watchpoint {}: {:03X} {} by",
+                    describe_watchpoint(&watchpoint, &self.symbols),
+                    access.address,
+                    verb
+                );
+                // The first access of an instruction is its opcode fetch
+                show_instruction(cpu, &self.symbols, cpu.accesses[0].address);
+            }
+            Some(Stop::Step) => show_instruction(cpu, &self.symbols, cpu.pc),
+            None => return,
+        }
+        prompt();
+    }
+}
+
+impl Monitor for Debugger {
+    /// Run the commands typed since the last frame.
+    fn poll(&mut self, cpu: &mut CPU) {
+        loop {
+            let command = if self.is_stopped() {
+                self.commands
+                    .recv_timeout(COMMAND_WAIT)
+                    .map_err(|error| error == RecvTimeoutError::Disconnected)
+            } else {
+                self.commands
+                    .try_recv()
+                    .map_err(|error| error == TryRecvError::Disconnected)
+            };
+
+            match command {
+                Ok(command) => {
+                    self.run(cpu, &command);
+                    if !self.is_stopped() {
+                        // Let the program run before the next commands
+                        break;
+                    }
+                    prompt();
+                }
+                // Without standard input nothing could resume the program,
+                // so let it run to the end
+                Err(true) => {
+                    self.control.detach();
+                    break;
+                }
+                Err(false) => break,
+            }
+        }
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.control.is_stopped()
+    }
+
+    fn before_instruction(&mut self, cpu: &CPU) -> bool {
+        let execute = self.control.before_instruction(cpu);
+        self.report_stop(cpu);
+        execute
+    }
+
+    fn after_instruction(&mut self, cpu: &CPU) {
+        self.control.after_instruction(cpu);
+        self.report_stop(cpu);
+    }
+}
+
+fn prompt() {
+    print!("(chip8) ");
+    std::io::stdout().flush().unwrap();
+}
+
+fn show_instruction(cpu: &CPU, symbols: &Symbols, address: u16) {
+    if let Some(name) = symbols.name(address) {
+        println!("{}:", name);
+    }
+    let opcode = read_opcode(cpu, address);
+    println!(
+        "{:03X}: {:04X}  {}",
+        address,
+        opcode,
+        symbols.disassemble(&cpu.jp, opcode)
+    );
+}
+
+fn describe_watchpoint(watchpoint: &Watchpoint, symbols: &Symbols) -> String {
+    let kinds: String = [
+        (watchpoint.read, 'r'),
+        (watchpoint.write, 'w'),
+        (watchpoint.execute, 'x'),
+    ]
+    .iter()
+    .filter(|(enabled, _)| *enabled)
+    .map(|(_, kind)| kind)
+    .collect();
+
+    let end = watchpoint.start + watchpoint.length - 1;
+    let range = if end == watchpoint.start {
+        format!("{:03X} {}", watchpoint.start, kinds)
+    } else {
+        format!("{:03X}-{:03X} {}", watchpoint.start, end, kinds)
+    };
+    match symbols.locate(watchpoint.start) {
+        Some(location) => format!("{} <{}>", range, location),
+        None => range,
+    }
+}
+
+fn read_opcode(cpu: &CPU, address: u16) -> u16 {
+    let high = cpu.memory[address as usize & 0xFFF] as u16;
+    let low = cpu.memory[(address as usize + 1) & 0xFFF] as u16;
+    (high << 8) | low
+}
+
+fn show_registers(cpu: &CPU) {
+    for (row, registers) in cpu.v.chunks(8).enumerate() {
+        let registers: Vec<String> = registers
+            .iter()
+            .enumerate()
+            .map(|(index, value)| format!("V{:X}={:02X}", row * 8 + index, value))
+            .collect();
+        println!("{}", registers.join(" "));
+    }
+    println!(
+        "I={:03X} PC={:03X} SP={:X} DT={:02X} ST={:02X}",
+        cpu.i, cpu.pc, cpu.number_jump, cpu.game_counter, cpu.sound_counter
+    );
+}
+
+fn set_register(cpu: &mut CPU, symbols: &Symbols, args: &[&str]) -> Result<(), String> {
+    let [register, value] = args else {
+        return Err("set expects a register and a value".to_string());
+    };
+    let value = match symbols.address(value) {
+        Some(address) => address,
+        None => parse_hex(value)?,
+    };
+    let byte = || u8::try_from(value).map_err(|_| format!("{:X} does not fit a byte", value));
+    let register = register.to_ascii_lowercase();
+
+    match register.as_str() {
+        "i" => cpu.i = value,
+        "pc" => cpu.pc = value & 0xFFF,
+        "dt" => cpu.game_counter = byte()?,
+        "st" => cpu.sound_counter = byte()?,
+        _ => {
+            let index = register
+                .strip_prefix('v')
+                .and_then(|index| u8::from_str_radix(index, 16).ok())
+                .filter(|index| *index < 16)
+                .ok_or(format!("unknown register: {}", register))?;
+            cpu.v[index as usize] = byte()?;
+        }
+    }
+    Ok(())
+}
+
+fn dump_memory(cpu: &CPU, symbols: &Symbols, args: &[&str]) -> Result<(), String> {
+    let address = parse_address(args.first().ok_or("mem expects an address")?, symbols)? as usize;
+    let length = match args.get(1) {
+        Some(length) => parse_count(length)? as usize,
+        None => 64,
+    };
+    let end = (address + length).min(cpu.memory.len());
+
+    for start in (address..end).step_by(16) {
+        let bytes: Vec<String> = cpu.memory[start..(start + 16).min(end)]
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        println!("{:03X}: {}", start, bytes.join(" "));
+    }
+    Ok(())
+}
+
+fn poke(cpu: &mut CPU, symbols: &Symbols, args: &[&str]) -> Result<(), String> {
+    let (address, bytes) = args.split_first().ok_or("poke expects an address")?;
+    let address = parse_address(address, symbols)? as usize;
+
+    for (offset, byte) in bytes.iter().enumerate() {
+        let byte = u8::from_str_radix(byte.trim_start_matches("0x"), 16)
+            .map_err(|_| format!("invalid byte: {}", byte))?;
+        cpu.memory[(address + offset) & 0xFFF] = byte;
+    }
+    Ok(())
+}
+
+fn show_stack(cpu: &CPU, symbols: &Symbols) {
+    if cpu.number_jump == 0 {
+        println!("Empty stack");
+    }
+    for (depth, address) in cpu.jump[..cpu.number_jump as usize]
+        .iter()
+        .enumerate()
+        .rev()
+    {
+        println!("#{} {}", depth, symbols.describe(*address));
+    }
+}
+
+fn disassemble(cpu: &CPU, symbols: &Symbols, args: &[&str]) -> Result<(), String> {
+    let address = match args.first() {
+        Some(address) => parse_address(address, symbols)?,
+        None => cpu.pc,
+    };
+    let count = match args.get(1) {
+        Some(count) => parse_count(count)?,
+        None => 10,
+    };
+    if count > MAX_DISASSEMBLY {
+        return Err(format!(
+            "cannot disassemble more than {} instructions",
+            MAX_DISASSEMBLY
+        ));
+    }
+
+    for index in 0..count as usize {
+        let address = (address as usize + index * 2) & 0xFFF;
+        show_instruction(cpu, symbols, address as u16);
+    }
+    Ok(())
+}
+
+fn parse_hex(text: &str) -> Result<u16, String> {
+    u16::from_str_radix(text.trim_start_matches("0x"), 16)
+        .map_err(|_| format!("invalid hexadecimal number: {}", text))
+}
+
+/// Parse a symbol, or else a hexadecimal address.
+fn parse_address(text: &str, symbols: &Symbols) -> Result<u16, String> {
+    if let Some(address) = symbols.address(text) {
+        return Ok(address);
+    }
+    let address = parse_hex(text).map_err(|_| format!("unknown address: {}", text))?;
+    match address {
+        address if address < 0x1000 => Ok(address),
+        address => Err(format!("address out of memory: {:X}", address)),
+    }
+}
+
+fn parse_count(text: &str) -> Result<u32, String> {
+    text.parse().map_err(|_| format!("invalid count: {}", text))
+}
@@ -0,0 +1,397 @@
+//! # Disassembler
+//!
+//! Mnemonics of the Chip8 instructions, in the syntax of Cowgod's technical
+//! reference or of Octo, and listings of whole programs. Instructions are
+//! decoded with the table of the CPU ([`crate::cpu::Jump`]) so that both
+//! always agree.
+
+use crate::cpu::Jump;
+use crate::symbols::Symbols;
+use std::collections::BTreeMap;
+
+/// Address programs are loaded at.
+pub const START: u16 = 0x200;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Syntax {
+    /// Cowgod's technical reference, e.g. `LD V1, #0A`.
+    Cowgod,
+    /// Octo assembly language, e.g. `v1 := 0x0A`.
+    Octo,
+}
+
+impl Syntax {
+    pub fn parse(name: &str) -> Option<Syntax> {
+        match name {
+            "cowgod" => Some(Syntax::Cowgod),
+            "octo" => Some(Syntax::Octo),
+            _ => None,
+        }
+    }
+
+    fn comment(&self) -> &str {
+        match self {
+            Syntax::Cowgod => ";",
+            Syntax::Octo => "#",
+        }
+    }
+}
+
+/// How an instruction refers to an address.
+#[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Ord)]
+pub enum Reference {
+    /// `2NNN` calls a subroutine.
+    Call,
+    /// `1NNN` jumps.
+    Jump,
+    /// `BNNN` jumps into a table indexed by V0.
+    Table,
+    /// `ANNN` points I to data.
+    Data,
+}
+
+impl Reference {
+    fn label(&self, address: u16) -> String {
+        let prefix = match self {
+            Reference::Call => "sub",
+            Reference::Jump => "label",
+            Reference::Table => "table",
+            Reference::Data => "data",
+        };
+        format!("{}_{:03X}", prefix, address)
+    }
+}
+
+/// Address an instruction refers to.
+pub fn reference(jp: &Jump, opcode: u16) -> Option<(Reference, u16)> {
+    let reference = match jp.get_action(opcode) {
+        3 => Reference::Jump,
+        4 => Reference::Call,
+        20 => Reference::Data,
+        21 => Reference::Table,
+        _ => return None,
+    };
+
+    Some((reference, opcode & 0x0FFF))
+}
+
+/// Mnemonic of an instruction in the Cowgod syntax. Opcodes which are not
+/// instructions are shown as data.
+pub fn disassemble(jp: &Jump, opcode: u16) -> String {
+    format_instruction(jp, opcode, Syntax::Cowgod, &|address| {
+        format!("#{:03X}", address)
+    })
+}
+
+/// Mnemonic of an instruction, `address` writing the addresses it refers to.
+pub fn format_instruction(
+    jp: &Jump,
+    opcode: u16,
+    syntax: Syntax,
+    address: &dyn Fn(u16) -> String,
+) -> String {
+    match syntax {
+        Syntax::Cowgod => cowgod(jp, opcode, address),
+        Syntax::Octo => octo(jp, opcode, address),
+    }
+}
+
+fn cowgod(jp: &Jump, opcode: u16, address: &dyn Fn(u16) -> String) -> String {
+    let x = (opcode & 0x0F00) >> 8;
+    let y = (opcode & 0x00F0) >> 4;
+    let n = opcode & 0x000F;
+    let nn = opcode & 0x00FF;
+    let nnn = opcode & 0x0FFF;
+
+    match jp.get_action(opcode) {
+        1 => "CLS".to_string(),
+        2 => "RET".to_string(),
+        3 => format!("JP {}", address(nnn)),
+        4 => format!("CALL {}", address(nnn)),
+        5 => format!("SE V{:X}, #{:02X}", x, nn),
+        6 => format!("SNE V{:X}, #{:02X}", x, nn),
+        7 => format!("SE V{:X}, V{:X}", x, y),
+        8 => format!("LD V{:X}, #{:02X}", x, nn),
+        9 => format!("ADD V{:X}, #{:02X}", x, nn),
+        10 => format!("LD V{:X}, V{:X}", x, y),
+        11 => format!("OR V{:X}, V{:X}", x, y),
+        12 => format!("AND V{:X}, V{:X}", x, y),
+        13 => format!("XOR V{:X}, V{:X}", x, y),
+        14 => format!("ADD V{:X}, V{:X}", x, y),
+        15 => format!("SUB V{:X}, V{:X}", x, y),
+        16 => format!("SHR V{:X}, V{:X}", x, y),
+        17 => format!("SUBN V{:X}, V{:X}", x, y),
+        18 => format!("SHL V{:X}, V{:X}", x, y),
+        19 => format!("SNE V{:X}, V{:X}", x, y),
+        20 => format!("LD I, {}", address(nnn)),
+        21 => format!("JP V0, {}", address(nnn)),
+        22 => format!("RND V{:X}, #{:02X}", x, nn),
+        23 => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        24 => format!("SKP V{:X}", x),
+        25 => format!("SKNP V{:X}", x),
+        26 => format!("LD V{:X}, DT", x),
+        27 => format!("LD V{:X}, K", x),
+        28 => format!("LD DT, V{:X}", x),
+        29 => format!("LD ST, V{:X}", x),
+        30 => format!("ADD I, V{:X}", x),
+        31 => format!("LD F, V{:X}", x),
+        32 => format!("LD B, V{:X}", x),
+        33 => format!("LD [I], V{:X}", x),
+        34 => format!("LD V{:X}, [I]", x),
+        35 => format!("SCD {}", n),
+        36 => "SCR".to_string(),
+        37 => "SCL".to_string(),
+        38 => "EXIT".to_string(),
+        39 => "LOW".to_string(),
+        40 => "HIGH".to_string(),
+        41 => format!("LD HF, V{:X}", x),
+        42 => format!("LD R, V{:X}", x),
+        43 => format!("LD V{:X}, R", x),
+        _ if opcode & 0xF000 == 0 => format!("SYS #{:03X}", nnn),
+        _ => format!("DW #{:04X}", opcode),
+    }
+}
+
+fn octo(jp: &Jump, opcode: u16, address: &dyn Fn(u16) -> String) -> String {
+    let x = (opcode & 0x0F00) >> 8;
+    let y = (opcode & 0x00F0) >> 4;
+    let n = opcode & 0x000F;
+    let nn = opcode & 0x00FF;
+    let nnn = opcode & 0x0FFF;
+
+    // Octo conditions tell when the next instruction is executed, which is
+    // the opposite of the skip condition
+    match jp.get_action(opcode) {
+        1 => "clear".to_string(),
+        2 => "return".to_string(),
+        3 => format!("jump {}", address(nnn)),
+        4 => {
+            // A bare label calls it, but a bare number is a byte of data
+            let target = address(nnn);
+            if target.starts_with(|c: char| c.is_ascii_digit()) {
+                format!(":call {}", target)
+            } else {
+                target
+            }
+        }
+        5 => format!("if v{:x} != 0x{:02X} then", x, nn),
+        6 => format!("if v{:x} == 0x{:02X} then", x, nn),
+        7 => format!("if v{:x} != v{:x} then", x, y),
+        8 => format!("v{:x} := 0x{:02X}", x, nn),
+        9 => format!("v{:x} += 0x{:02X}", x, nn),
+        10 => format!("v{:x} := v{:x}", x, y),
+        11 => format!("v{:x} |= v{:x}", x, y),
+        12 => format!("v{:x} &= v{:x}", x, y),
+        13 => format!("v{:x} ^= v{:x}", x, y),
+        14 => format!("v{:x} += v{:x}", x, y),
+        15 => format!("v{:x} -= v{:x}", x, y),
+        16 => format!("v{:x} >>= v{:x}", x, y),
+        17 => format!("v{:x} =- v{:x}", x, y),
+        18 => format!("v{:x} <<= v{:x}", x, y),
+        19 => format!("if v{:x} == v{:x} then", x, y),
+        20 => format!("i := {}", address(nnn)),
+        21 => format!("jump0 {}", address(nnn)),
+        22 => format!("v{:x} := random 0x{:02X}", x, nn),
+        23 => format!("sprite v{:x} v{:x} {}", x, y, n),
+        24 => format!("if v{:x} -key then", x),
+        25 => format!("if v{:x} key then", x),
+        26 => format!("v{:x} := delay", x),
+        27 => format!("v{:x} := key", x),
+        28 => format!("delay := v{:x}", x),
+        29 => format!("buzzer := v{:x}", x),
+        30 => format!("i += v{:x}", x),
+        31 => format!("i := hex v{:x}", x),
+        32 => format!("bcd v{:x}", x),
+        33 => format!("save v{:x}", x),
+        34 => format!("load v{:x}", x),
+        35 => format!("scroll-down {}", n),
+        36 => "scroll-right".to_string(),
+        37 => "scroll-left".to_string(),
+        38 => "exit".to_string(),
+        39 => "lores".to_string(),
+        40 => "hires".to_string(),
+        41 => format!("i := bighex v{:x}", x),
+        42 => format!("saveflags v{:x}", x),
+        43 => format!("loadflags v{:x}", x),
+        _ => format!("0x{:02X} 0x{:02X}", opcode >> 8, opcode & 0xFF),
+    }
+}
+
+/// Addresses the execution can continue at after an instruction, as far as
+/// it can be told without running the program.
+fn successors(jp: &Jump, address: u16, opcode: u16) -> Vec<u16> {
+    let nnn = opcode & 0x0FFF;
+
+    match jp.get_action(opcode) {
+        // Return, exit, or an opcode which is not an instruction
+        0 | 2 | 38 => Vec::new(),
+        3 => vec![nnn],
+        4 => vec![nnn, address + 2],
+        // The entries of a jump table are not known, only its start
+        21 => vec![nnn],
+        // Conditional skips
+        5 | 6 | 7 | 19 | 24 | 25 => vec![address + 2, address + 4],
+        _ => vec![address + 2],
+    }
+}
+
+/// Listing of a program loaded at [`START`]: addresses, opcodes and
+/// mnemonics, with labels for the addresses the instructions refer to, named
+/// after the symbols when they have one. The bytes the execution cannot reach
+/// are shown as data, with their bits drawn like a sprite.
+pub fn listing(rom: &[u8], syntax: Syntax, symbols: &Symbols) -> String {
+    let jp = Jump::new();
+    let end = START as usize + rom.len();
+    let opcode = |address: u16| {
+        let offset = (address - START) as usize;
+        ((rom[offset] as u16) << 8) | rom[offset + 1] as u16
+    };
+    let in_rom = |address: u16| address >= START && (address as usize) + 1 < end;
+
+    // Follow the execution from the start to find the instructions
+    let mut code = BTreeMap::new();
+    let mut pending = vec![START];
+    while let Some(address) = pending.pop() {
+        if !in_rom(address) || code.contains_key(&address) {
+            continue;
+        }
+        code.insert(address, opcode(address));
+        pending.extend(successors(&jp, address, opcode(address)));
+    }
+
+    // Lines of the listing: an instruction or a byte of data
+    let mut lines = Vec::new();
+    let mut address = START;
+    while (address as usize) < end {
+        let is_code = code.contains_key(&address);
+        lines.push((address, is_code));
+        address += if is_code { 2 } else { 1 };
+    }
+
+    // Only addresses starting a line can be labelled, the strongest
+    // reference naming the label
+    let mut labels: BTreeMap<u16, Reference> = BTreeMap::new();
+    for opcode in code.values() {
+        if let Some((kind, target)) = reference(&jp, *opcode) {
+            if lines
+                .binary_search_by_key(&target, |(address, _)| *address)
+                .is_ok()
+            {
+                let label = labels.entry(target).or_insert(kind);
+                *label = (*label).min(kind);
+            }
+        }
+    }
+    let label = |address: u16| match symbols.name(address) {
+        Some(name) => Some(name.to_string()),
+        None => labels.get(&address).map(|kind| kind.label(address)),
+    };
+    let address_name = |address: u16| match label(address) {
+        Some(label) => label,
+        None if syntax == Syntax::Octo => format!("0x{:03X}", address),
+        None => format!("#{:03X}", address),
+    };
+
+    let comment = syntax.comment();
+    let mut text = String::new();
+    for (address, is_code) in lines {
+        if let Some(label) = label(address) {
+            match syntax {
+                Syntax::Cowgod => text.push_str(&format!("{}:\n", label)),
+                Syntax::Octo => text.push_str(&format!(": {}\n", label)),
+            }
+        }
+
+        if is_code {
+            let opcode = code[&address];
+            let mnemonic = format_instruction(&jp, opcode, syntax, &address_name);
+            text.push_str(&match syntax {
+                Syntax::Cowgod => format!("{:03X}: {:04X}  {}\n", address, opcode, mnemonic),
+                Syntax::Octo => format!(
+                    "\t{:<24} {} {:03X}: {:04X}\n",
+                    mnemonic, comment, address, opcode
+                ),
+            });
+        } else {
+            let byte = rom[(address - START) as usize];
+            let art: String = (0..8)
+                .map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' })
+                .collect();
+            text.push_str(&match syntax {
+                Syntax::Cowgod => format!(
+                    "{:03X}: {:02X}    DB #{:02X}  {} {}\n",
+                    address, byte, byte, comment, art
+                ),
+                Syntax::Octo => format!(
+                    "\t0x{:02X}{:<20} {} {:03X}: {}\n",
+                    byte, "", comment, address, art
+                ),
+            });
+        }
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Jumps over a sprite, points I to it, then stops at an unknown opcode
+    // which hides the bytes after it
+    const ROM: [u8; 10] = [0x12, 0x04, 0xF0, 0x90, 0xA2, 0x02, 0x50, 0x01, 0x12, 0x06];
+
+    #[test]
+    fn separates_code_and_data() {
+        assert_eq!(
+            listing(&ROM, Syntax::Cowgod, &Symbols::default()),
+            "200: 1204  JP label_204\n\
+             data_202:\n\
+             202: F0    DB #F0  ; ####....\n\
+             203: 90    DB #90  ; #..#....\n\
+             label_204:\n\
+             204: A202  LD I, data_202\n\
+             206: 5001  DW #5001\n\
+             208: 12    DB #12  ; ...#..#.\n\
+             209: 06    DB #06  ; .....##.\n"
+        );
+    }
+
+    #[test]
+    fn writes_unknown_opcodes_as_raw_words() {
+        let listing = listing(&ROM, Syntax::Octo, &Symbols::default());
+        let lines: Vec<&str> = listing.lines().map(str::trim).collect();
+
+        assert_eq!(
+            lines,
+            [
+                "jump label_204           # 200: 1204",
+                ": data_202",
+                "0xF0                     # 202: ####....",
+                "0x90                     # 203: #..#....",
+                ": label_204",
+                "i := data_202            # 204: A202",
+                "0x50 0x01                # 206: 5001",
+                "0x12                     # 208: ...#..#.",
+                "0x06                     # 209: .....##.",
+            ]
+        );
+    }
+    #[test]
+    fn names_calls_in_the_octo_syntax() {
+        // A call out of the ROM, a call to a subroutine, then the subroutine
+        let rom = [0x21, 0x00, 0x22, 0x04, 0x00, 0xEE];
+        let listing = listing(&rom, Syntax::Octo, &Symbols::default());
+        let lines: Vec<&str> = listing.lines().map(str::trim).collect();
+
+        assert_eq!(
+            lines,
+            [
+                ":call 0x100              # 200: 2100",
+                "sub_204                  # 202: 2204",
+                ": sub_204",
+                "return                   # 204: 00EE",
+            ]
+        );
+    }
+}
@@ -0,0 +1,61 @@
+//! # Frontend
+//!
+//! A frontend displays the Chip8 screen and reads the keyboard. The emulator
+//! can run either in a window ([`crate::pixel::PixelDisplay`]), directly in
+//! a terminal ([`crate::terminal::TerminalDisplay`]), or without any display
+//! ([`Headless`]).
+
+use crate::osd::Osd;
+use crate::pixel::Screen;
+use minifb::Key;
+
+pub trait Frontend {
+    /// Present the Chip8 screen, redrawing only the rows which changed, with
+    /// the on-screen display over it if the frontend supports it.
+    fn draw(&mut self, screen: &Screen, osd: &Osd);
+
+    /// Whether the user has not closed the frontend yet.
+    fn is_open(&self) -> bool;
+
+    /// Every host key held down, sampled once per frame. A key pressed and
+    /// released since the last frame is reported as held, so that short taps
+    /// are not missed.
+    fn get_keys_down(&mut self) -> Vec<Key>;
+
+    /// Chip8 keys held down on the virtual keypad, if the frontend has one.
+    fn get_virtual_keys(&mut self) -> [bool; 16] {
+        [false; 16]
+    }
+
+    /// Show on the virtual keypad the keys held down and the keys the
+    /// program read during the last frame.
+    fn show_keypad(&mut self, _keys: [bool; 16], _polled: [bool; 16]) {}
+
+    /// Whether the key was just pressed, to trigger emulator shortcuts.
+    fn is_key_pressed(&mut self, key: Key) -> bool;
+
+    /// Switch between the normal and the fullscreen window, telling why if
+    /// the frontend cannot.
+    fn toggle_fullscreen(&mut self) -> Result<(), String> {
+        Err("Fullscreen is only available in a window".to_string())
+    }
+}
+
+/// Frontend which displays nothing and has no keyboard, for automated runs.
+pub struct Headless;
+
+impl Frontend for Headless {
+    fn draw(&mut self, _screen: &Screen, _osd: &Osd) {}
+
+    fn is_open(&self) -> bool {
+        true
+    }
+
+    fn get_keys_down(&mut self) -> Vec<Key> {
+        Vec::new()
+    }
+
+    fn is_key_pressed(&mut self, _key: Key) -> bool {
+        false
+    }
+}
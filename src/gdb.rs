@@ -0,0 +1,517 @@
+//! # GDB stub
+//!
+//! Server of the GDB remote serial protocol on a local TCP port, to debug the
+//! program with GDB or any other front end speaking the protocol.
+//!
+//! The registers are numbered in this order, I and PC being 16 bits wide in
+//! little endian, the others 8 bits wide:
+//!
+//! ```text
+//! 0-15  V0 to VF
+//! 16    I
+//! 17    PC
+//! 18    SP, the depth of the call stack
+//! 19    DT
+//! 20    ST
+//! ```
+//!
+//! The address space is the 4 KiB of memory. Breakpoints, read, write and
+//! access watchpoints, single step, continue, interruption and killing the
+//! program, which ends the emulation, are supported.
+
+use crate::cpu::CPU;
+use crate::monitor::{Control, Monitor, Stop, Watchpoint};
+use std::io::{BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::time::Duration;
+
+// How long to wait for a packet at every frame while the program is stopped
+const PACKET_WAIT: Duration = Duration::from_millis(16);
+
+// Signals of the stop replies
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+const REGISTER_COUNT: usize = 21;
+
+enum Packet {
+    Command(String),
+    /// Ctrl-C
+    Interrupt,
+}
+
+pub struct GdbStub {
+    packets: Receiver<Packet>,
+    stream: TcpStream,
+    control: Control,
+    // GDB killed the program
+    killed: bool,
+}
+
+impl GdbStub {
+    /// Wait for a debugger to connect to `port`, the program being stopped on
+    /// its first instruction.
+    pub fn listen(port: u16) -> Result<GdbStub, String> {
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .map_err(|error| format!("cannot listen on port {}: {}", port, error))?;
+        println!("Waiting for GDB on port {}", port);
+        let (stream, address) = listener
+            .accept()
+            .map_err(|error| format!("cannot accept GDB: {}", error))?;
+        println!("GDB connected from {}", address);
+
+        // Reading the connection blocks, so wait for packets in another
+        // thread and receive them at every frame
+        let reader = stream
+            .try_clone()
+            .map_err(|error| format!("cannot read from GDB: {}", error))?;
+        let (sender, packets) = mpsc::channel();
+        std::thread::spawn(move || read_packets(reader, sender));
+
+        Ok(GdbStub {
+            packets,
+            stream,
+            control: Control::new(),
+            killed: false,
+        })
+    }
+
+    fn send(&mut self, data: &str) {
+        // A lost connection is noticed by the reading thread
+        let _ = self.stream.write_all(&encode(data));
+    }
+
+    /// Send the stop reply telling why the program stopped, if it did.
+    fn report_stop(&mut self) {
+        let reply = match self.control.take_stop() {
+            Some(Stop::Watchpoint(watchpoint, access)) => {
+                let name = match (watchpoint.read, watchpoint.write) {
+                    (false, _) => "watch",
+                    (_, false) => "rwatch",
+                    _ => "awatch",
+                };
+                format!("T{:02x}{}:{:x};", SIGTRAP, name, access.address)
+            }
+            Some(Stop::Breakpoint | Stop::Step) => format!("S{:02x}", SIGTRAP),
+            None => return,
+        };
+        self.send(&reply);
+    }
+
+    /// Reply to a command, or `None` when the reply is sent once the program
+    /// stops.
+    fn run(&mut self, cpu: &mut CPU, command: &str) -> Option<String> {
+        let Some(name) = command.chars().next() else {
+            return Some(String::new());
+        };
+        let args = &command[name.len_utf8()..];
+
+        let reply = match name {
+            '?' => format!("S{:02x}", SIGTRAP),
+            'g' => (0..REGISTER_COUNT)
+                .filter_map(|number| register(cpu, number))
+                .map(|bytes| to_hex(&bytes))
+                .collect(),
+            'G' => status(write_registers(cpu, args)),
+            'p' => usize::from_str_radix(args, 16)
+                .ok()
+                .and_then(|number| register(cpu, number))
+                .map_or("E01".to_string(), |bytes| to_hex(&bytes)),
+            'P' => status(write_register(cpu, args)),
+            'm' => read_memory(cpu, args).unwrap_or("E01".to_string()),
+            'M' => status(write_memory(cpu, args)),
+            'c' | 's' => {
+                // The optional argument is the address to resume at
+                if !args.is_empty() {
+                    match u16::from_str_radix(args, 16) {
+                        Ok(address) => cpu.pc = address & 0xFFF,
+                        Err(_) => return Some("E01".to_string()),
+                    }
+                }
+                if name == 'c' {
+                    self.control.resume();
+                } else {
+                    self.control.step(1);
+                }
+                return None;
+            }
+            'Z' | 'z' => self.set_point(name == 'Z', args),
+            'D' => {
+                self.control.detach();
+                "OK".to_string()
+            }
+            'k' => {
+                self.killed = true;
+                return None;
+            }
+            'H' | 'T' => "OK".to_string(),
+            'q' => query(args),
+            _ => String::new(),
+        };
+
+        Some(reply)
+    }
+
+    /// Add or remove a breakpoint or watchpoint: `<type>,<address>,<kind>`,
+    /// the kind of watchpoints being their length.
+    fn set_point(&mut self, add: bool, args: &str) -> String {
+        let fields: Vec<&str> = args.split(',').collect();
+        let [kind, address, length] = fields[..] else {
+            return "E01".to_string();
+        };
+        let (Ok(address), Ok(length)) = (
+            u16::from_str_radix(address, 16),
+            u16::from_str_radix(length, 16),
+        ) else {
+            return "E01".to_string();
+        };
+
+        let (read, write) = match kind {
+            "0" | "1" => {
+                let breakpoints = &mut self.control.breakpoints;
+                breakpoints.retain(|breakpoint| *breakpoint != address);
+                if add {
+                    breakpoints.push(address);
+                }
+                return "OK".to_string();
+            }
+            "2" => (false, true),
+            "3" => (true, false),
+            "4" => (true, true),
+            _ => return String::new(),
+        };
+
+        let watchpoint = Watchpoint {
+            start: address,
+            length,
+            read,
+            write,
+            execute: false,
+        };
+        let watchpoints = &mut self.control.watchpoints;
+        watchpoints.retain(|other| *other != watchpoint);
+        if add {
+            watchpoints.push(watchpoint);
+        }
+        "OK".to_string()
+    }
+}
+
+impl Monitor for GdbStub {
+    /// Answer the packets received since the last frame.
+    fn poll(&mut self, cpu: &mut CPU) {
+        loop {
+            let packet = if self.is_stopped() {
+                self.packets
+                    .recv_timeout(PACKET_WAIT)
+                    .map_err(|error| error == RecvTimeoutError::Disconnected)
+            } else {
+                self.packets
+                    .try_recv()
+                    .map_err(|error| error == TryRecvError::Disconnected)
+            };
+
+            match packet {
+                Ok(Packet::Command(command)) => {
+                    if let Some(reply) = self.run(cpu, &command) {
+                        self.send(&reply);
+                    }
+                    if self.killed || !self.is_stopped() {
+                        // Let the program run before the next packets
+                        break;
+                    }
+                }
+                Ok(Packet::Interrupt) => {
+                    self.control.stop();
+                    self.send(&format!("S{:02x}", SIGINT));
+                }
+                // Without debugger nothing could resume the program, so let it
+                // run to the end
+                Err(true) => {
+                    self.control.detach();
+                    break;
+                }
+                Err(false) => break,
+            }
+        }
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.control.is_stopped()
+    }
+
+    fn is_over(&self) -> bool {
+        self.killed
+    }
+
+    fn before_instruction(&mut self, cpu: &CPU) -> bool {
+        let execute = self.control.before_instruction(cpu);
+        self.report_stop();
+        execute
+    }
+
+    fn after_instruction(&mut self, cpu: &CPU) {
+        self.control.after_instruction(cpu);
+        self.report_stop();
+    }
+}
+
+/// Receive the packets, acknowledging them, until the connection is closed.
+fn read_packets(stream: TcpStream, sender: Sender<Packet>) {
+    let Ok(mut acks) = stream.try_clone() else {
+        return;
+    };
+    let mut bytes = BufReader::new(stream).bytes().map_while(Result::ok);
+
+    while let Some(byte) = bytes.next() {
+        let packet = match byte {
+            0x03 => Packet::Interrupt,
+            b'$' => {
+                let data: Vec<u8> = bytes.by_ref().take_while(|byte| *byte != b'#').collect();
+                let checksum: String = bytes.by_ref().take(2).map(char::from).collect();
+                let command = decode(&data, &checksum);
+
+                // A packet with a wrong checksum is sent again
+                let _ = acks.write_all(if command.is_some() { b"+" } else { b"-" });
+                match command {
+                    Some(command) => Packet::Command(command),
+                    None => continue,
+                }
+            }
+            // Acknowledgements of the replies
+            _ => continue,
+        };
+
+        if sender.send(packet).is_err() {
+            break;
+        }
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+/// Frame a reply, escaping the characters which delimit packets with `}`
+/// followed by the character xored with 0x20.
+fn encode(data: &str) -> Vec<u8> {
+    let mut escaped = Vec::new();
+    for byte in data.bytes() {
+        if matches!(byte, b'$' | b'#' | b'}' | b'*') {
+            escaped.extend([b'}', byte ^ 0x20]);
+        } else {
+            escaped.push(byte);
+        }
+    }
+
+    let mut packet = vec![b'$'];
+    packet.extend(&escaped);
+    packet.extend(format!("#{:02x}", checksum(&escaped)).bytes());
+    packet
+}
+
+/// Command of the data of a packet, if it matches the checksum, which is
+/// computed before the escaped characters are restored.
+fn decode(data: &[u8], checksum_text: &str) -> Option<String> {
+    if u8::from_str_radix(checksum_text, 16) != Ok(checksum(data)) {
+        return None;
+    }
+
+    let mut command = Vec::new();
+    let mut bytes = data.iter();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'}' => command.push(bytes.next()? ^ 0x20),
+            _ => command.push(*byte),
+        }
+    }
+    Some(String::from_utf8_lossy(&command).into_owned())
+}
+
+fn query(args: &str) -> String {
+    if args.starts_with("Supported") {
+        return "PacketSize=4000;qXfer:features:read+".to_string();
+    }
+    if let Some(range) = args.strip_prefix("Xfer:features:read:target.xml:") {
+        let Some((offset, length)) = range.split_once(',') else {
+            return "E01".to_string();
+        };
+        let (Ok(offset), Ok(length)) = (
+            usize::from_str_radix(offset, 16),
+            usize::from_str_radix(length, 16),
+        ) else {
+            return "E01".to_string();
+        };
+
+        let start = offset.min(TARGET_XML.len());
+        let end = (offset + length).min(TARGET_XML.len());
+        let more = if end < TARGET_XML.len() { 'm' } else { 'l' };
+        return format!("{}{}", more, &TARGET_XML[start..end]);
+    }
+
+    // A single thread
+    match args {
+        "Attached" => "1".to_string(),
+        "C" => "QC1".to_string(),
+        "fThreadInfo" => "m1".to_string(),
+        "sThreadInfo" => "l".to_string(),
+        _ => String::new(),
+    }
+}
+
+fn register(cpu: &CPU, number: usize) -> Option<Vec<u8>> {
+    Some(match number {
+        0..=15 => vec![cpu.v[number]],
+        16 => cpu.i.to_le_bytes().to_vec(),
+        17 => cpu.pc.to_le_bytes().to_vec(),
+        18 => vec![cpu.number_jump],
+        19 => vec![cpu.game_counter],
+        20 => vec![cpu.sound_counter],
+        _ => return None,
+    })
+}
+
+fn set_register(cpu: &mut CPU, number: usize, bytes: &[u8]) -> Option<()> {
+    match (number, bytes) {
+        (0..=15, [value]) => cpu.v[number] = *value,
+        (16, [low, high]) => cpu.i = u16::from_le_bytes([*low, *high]),
+        (17, [low, high]) => cpu.pc = u16::from_le_bytes([*low, *high]) & 0xFFF,
+        (18, [depth]) if (*depth as usize) < cpu.jump.len() => cpu.number_jump = *depth,
+        (19, [value]) => cpu.game_counter = *value,
+        (20, [value]) => cpu.sound_counter = *value,
+        _ => return None,
+    }
+    Some(())
+}
+
+/// `G<registers>`: every register, in order.
+fn write_registers(cpu: &mut CPU, args: &str) -> Option<()> {
+    let bytes = from_hex(args)?;
+    let mut offset = 0;
+
+    for number in 0..REGISTER_COUNT {
+        let size = register(cpu, number)?.len();
+        set_register(cpu, number, bytes.get(offset..offset + size)?)?;
+        offset += size;
+    }
+    Some(())
+}
+
+/// `P<number>=<value>`
+fn write_register(cpu: &mut CPU, args: &str) -> Option<()> {
+    let (number, value) = args.split_once('=')?;
+    set_register(
+        cpu,
+        usize::from_str_radix(number, 16).ok()?,
+        &from_hex(value)?,
+    )
+}
+
+/// Parse `<address>,<length>`, in memory.
+fn memory_range(cpu: &CPU, args: &str) -> Option<(usize, usize)> {
+    let (address, length) = args.split_once(',')?;
+    let address = usize::from_str_radix(address, 16).ok()?;
+    let length = usize::from_str_radix(length, 16).ok()?;
+
+    (address + length <= cpu.memory.len()).then_some((address, length))
+}
+
+/// `m<address>,<length>`
+fn read_memory(cpu: &CPU, args: &str) -> Option<String> {
+    let (address, length) = memory_range(cpu, args)?;
+    Some(to_hex(&cpu.memory[address..address + length]))
+}
+
+/// `M<address>,<length>:<bytes>`
+fn write_memory(cpu: &mut CPU, args: &str) -> Option<()> {
+    let (range, bytes) = args.split_once(':')?;
+    let (address, length) = memory_range(cpu, range)?;
+    let bytes = from_hex(bytes)?;
+    if bytes.len() != length {
+        return None;
+    }
+
+    cpu.memory[address..address + length].copy_from_slice(&bytes);
+    Some(())
+}
+
+fn status(result: Option<()>) -> String {
+    match result {
+        Some(()) => "OK".to_string(),
+        None => "E01".to_string(),
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_replies() {
+        assert_eq!(encode("OK"), b"$OK#9a");
+        assert_eq!(encode(""), b"$#00");
+        // The checksum is the one of the escaped data
+        assert_eq!(encode("a}b#c"), b"$a}]b}\x03c#80");
+    }
+
+    #[test]
+    fn decodes_commands() {
+        assert_eq!(decode(b"m200,4", "5f"), Some("m200,4".to_string()));
+        assert_eq!(decode(b"m200,4", "5F"), Some("m200,4".to_string()));
+        assert_eq!(decode(b"X200,1:}]", "5b"), Some("X200,1:}".to_string()));
+    }
+
+    #[test]
+    fn rejects_wrong_checksums() {
+        assert_eq!(decode(b"m200,4", "5e"), None);
+        assert_eq!(decode(b"m200,4", "5"), None);
+        assert_eq!(decode(b"m200,4", "zz"), None);
+        // An escape character ending the data
+        assert_eq!(decode(b"}", "7d"), None);
+    }
+}
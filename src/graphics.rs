@@ -0,0 +1,291 @@
+//! # Graphics
+//!
+//! Bitmap output for terminals supporting the Sixel or the Kitty graphics
+//! protocol, and detection of which one the terminal understands.
+
+use crate::pixel::{Screen, LENGTH_HEIGHT, LENGTH_WIDTH};
+use std::io::Write;
+use std::time::Duration;
+
+const QUERY_TIMEOUT: Duration = Duration::from_millis(500);
+const KITTY_CHUNK_SIZE: usize = 4096;
+
+/// Color of a bitmap pixel.
+pub type Rgb = [u8; 3];
+
+/// Bitmap protocol understood by the terminal.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Protocol {
+    Sixel,
+    Kitty,
+}
+
+/// Ask the terminal which bitmap protocol it supports. The terminal must be
+/// in raw mode so that the answer is not echoed.
+///
+/// A Kitty graphics query is sent followed by a primary device attributes
+/// request. Every terminal answers the latter, and those supporting Sixel
+/// list the attribute 4. Terminals supporting the Kitty protocol answer the
+/// first query before.
+pub fn detect() -> Option<Protocol> {
+    let mut stdout = std::io::stdout();
+    write!(stdout, "\x1b_Gi=31,s=1,v=1,a=q,t=d,f=24;AAAA\x1b\\\x1b[c").ok()?;
+    stdout.flush().ok()?;
+
+    parse_answer(&String::from_utf8_lossy(&read_answer()))
+}
+
+/// Protocol supported according to the answer to the queries of
+/// [`detect`], which can be truncated or mixed with typed keys.
+fn parse_answer(answer: &str) -> Option<Protocol> {
+    if answer.contains("_Gi=31;OK") {
+        return Some(Protocol::Kitty);
+    }
+
+    // The device attributes are `ESC [ ? <attributes> c`
+    let start = answer.find("\x1b[?")? + 3;
+    let end = start + answer[start..].find('c')?;
+    if answer[start..end]
+        .split(';')
+        .any(|attribute| attribute == "4")
+    {
+        return Some(Protocol::Sixel);
+    }
+
+    None
+}
+
+/// Read the answer to the queries, giving up if the terminal does not answer
+/// in time. The bytes are read one by one from the file descriptor, bypassing
+/// the buffer of `std::io::stdin()`, so that the keys typed after the answer
+/// are left for the frontend.
+#[cfg(unix)]
+fn read_answer() -> Vec<u8> {
+    use std::os::unix::io::AsRawFd;
+    use std::time::Instant;
+
+    let fd = std::io::stdin().as_raw_fd();
+    let deadline = Instant::now() + QUERY_TIMEOUT;
+    let mut answer = Vec::new();
+
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break;
+        }
+
+        let mut poll_fd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: the pointer is to a single valid pollfd
+        let ready = unsafe { libc::poll(&mut poll_fd, 1, left.as_millis() as libc::c_int) };
+        if ready < 0 && std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted {
+            continue;
+        }
+        if ready <= 0 {
+            break;
+        }
+
+        let mut byte = 0u8;
+        // SAFETY: the buffer is a single byte, and stdin is ready to be read
+        let count = unsafe { libc::read(fd, (&mut byte as *mut u8).cast(), 1) };
+        if count != 1 {
+            break;
+        }
+        answer.push(byte);
+
+        // The device attributes answer is `ESC [ ? ... c` and comes last
+        if byte == b'c' && answer.windows(3).any(|window| window == b"\x1b[?") {
+            break;
+        }
+    }
+
+    answer
+}
+
+/// Terminals are only queried on Unix, elsewhere no protocol is detected.
+#[cfg(not(unix))]
+fn read_answer() -> Vec<u8> {
+    Vec::new()
+}
+
+/// Whether the pixel of a `width` x `height` image of the screen is on, the
+/// image having the same size in both resolutions.
+fn is_on(screen: &Screen, x: usize, y: usize, width: usize, height: usize) -> bool {
+    let x = x * screen.width() as usize / width;
+    let y = y * screen.height() as usize / height;
+    screen.is_on(x as u16, y as u16)
+}
+
+/// Encode the screen as a Sixel image, each Chip8 pixel being a square of
+/// `scale` pixels, or half of it in high resolution. `colors` are the colors
+/// of the pixels which are on and off.
+pub fn sixel(screen: &Screen, scale: usize, colors: [Rgb; 2]) -> String {
+    let width = LENGTH_WIDTH as usize * scale;
+    let height = LENGTH_HEIGHT as usize * scale;
+    let on = |x: usize, y: usize| is_on(screen, x, y, width, height);
+
+    // Two color registers: background and foreground, in percents
+    let register = |[red, green, blue]: Rgb| {
+        let percent = |value: u8| value as usize * 100 / 255;
+        format!("2;{};{};{}", percent(red), percent(green), percent(blue))
+    };
+    let mut output = format!(
+        "\x1bPq\"1;1;{};{}#0;{}#1;{}",
+        width,
+        height,
+        register(colors[1]),
+        register(colors[0])
+    );
+
+    // A sixel encodes a column of 6 pixels, the image is drawn by bands of 6
+    // rows, each color being painted over the same band
+    for band in (0..height).step_by(6) {
+        for color in [false, true] {
+            output.push_str(if color { "#1" } else { "#0" });
+
+            let sixels = (0..width).map(|x| {
+                let mut bits = 0;
+                for row in 0..6 {
+                    if band + row < height && on(x, band + row) == color {
+                        bits |= 1 << row;
+                    }
+                }
+                (63 + bits) as u8 as char
+            });
+            push_run_length(&mut output, sixels);
+
+            output.push(if color { '-' } else { '$' });
+        }
+    }
+
+    output.push_str("\x1b\\");
+    output
+}
+
+/// Append sixels to the output, replacing repeated sixels by `!<count><sixel>`.
+fn push_run_length(output: &mut String, sixels: impl Iterator<Item = char>) {
+    let mut run: Option<(char, usize)> = None;
+
+    for sixel in sixels {
+        run = match run {
+            Some((current, count)) if current == sixel => Some((current, count + 1)),
+            _ => {
+                push_run(output, run);
+                Some((sixel, 1))
+            }
+        };
+    }
+    push_run(output, run);
+}
+
+fn push_run(output: &mut String, run: Option<(char, usize)>) {
+    match run {
+        Some((sixel, count)) if count > 3 => output.push_str(&format!("!{}{}", count, sixel)),
+        Some((sixel, count)) => output.extend(std::iter::repeat_n(sixel, count)),
+        None => {}
+    }
+}
+
+/// Encode the screen as a Kitty graphics protocol image, each Chip8 pixel
+/// being a square of `scale` pixels, or half of it in high resolution, with
+/// the `colors` of the pixels which are on and off. The image and its
+/// placement reuse the same ids so that every frame replaces the previous one.
+pub fn kitty(screen: &Screen, scale: usize, colors: [Rgb; 2]) -> String {
+    let width = LENGTH_WIDTH as usize * scale;
+    let height = LENGTH_HEIGHT as usize * scale;
+
+    let mut data = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            let color = if is_on(screen, x, y, width, height) {
+                colors[0]
+            } else {
+                colors[1]
+            };
+            data.extend_from_slice(&color);
+        }
+    }
+    let data = base64(&data);
+
+    // The payload is sent by chunks, `m=1` telling more chunks follow
+    let mut output = String::new();
+    let chunks: Vec<&[u8]> = data.as_bytes().chunks(KITTY_CHUNK_SIZE).collect();
+    for (index, chunk) in chunks.iter().enumerate() {
+        let more = (index + 1 < chunks.len()) as u8;
+        if index == 0 {
+            output.push_str(&format!(
+                "\x1b_Ga=T,f=24,s={},v={},i=1,p=1,q=2,C=1,m={};",
+                width, height, more
+            ));
+        } else {
+            output.push_str(&format!("\x1b_Gm={};", more));
+        }
+        output.push_str(std::str::from_utf8(chunk).unwrap());
+        output.push_str("\x1b\\");
+    }
+
+    output
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut output = String::with_capacity(data.len().div_ceil(3) * 4);
+    for group in data.chunks(3) {
+        let bytes = [
+            group[0],
+            *group.get(1).unwrap_or(&0),
+            *group.get(2).unwrap_or(&0),
+        ];
+        let value = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+
+        for index in 0..4 {
+            if index <= group.len() {
+                output.push(ALPHABET[(value >> (18 - 6 * index) & 0x3F) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_protocol_in_the_answer() {
+        assert!(parse_answer("\x1b_Gi=31;OK\x1b\\\x1b[?62;22c") == Some(Protocol::Kitty));
+        assert!(parse_answer("\x1b[?62;4;22c") == Some(Protocol::Sixel));
+        assert!(parse_answer("\x1b[?4c") == Some(Protocol::Sixel));
+        assert!(parse_answer("\x1b[?62;22c").is_none());
+        assert!(parse_answer("\x1b[?62;14;22c").is_none());
+    }
+
+    #[test]
+    fn draws_bitmaps_in_the_colors() {
+        let screen = Screen::new();
+        let colors = [[0, 255, 0], [0, 0, 238]];
+
+        let sixel = sixel(&screen, 1, colors);
+        assert!(sixel.starts_with("\x1bPq\"1;1;64;32#0;2;0;0;93#1;2;0;100;0"));
+
+        // Every pixel is off, and 3 pixels are 4 characters of base64
+        let kitty = kitty(&screen, 1, colors);
+        assert!(kitty.contains(";AADuAADuAADuAADu"));
+    }
+
+    #[test]
+    fn ignores_truncated_and_garbage_answers() {
+        assert!(parse_answer("").is_none());
+        assert!(parse_answer("\x1b[?62;4").is_none());
+        assert!(parse_answer("c\x1b[?62;4").is_none());
+        assert!(parse_answer("abc\x1b[?").is_none());
+        assert!(parse_answer("\x1b_Gi=31;ERR\x1b\\").is_none());
+    }
+}
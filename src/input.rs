@@ -0,0 +1,228 @@
+//! # Input
+//!
+//! Sources the main loop reads the Chip8 keypad from, once per frame: the
+//! live keyboard, a movie ([`crate::movie::Player`]) or a script.
+//!
+//! A script presses and releases keys at given frames, paused frames not
+//! being counted. Statements are separated by new lines or `;`:
+//!
+//! ```text
+//! # Start the game, then hold 5 for 10 frames
+//! frame 60: press 1; frame 61: release 1
+//! frame 120: press 5
+//! frame 130: release 5
+//! frame 600: end
+//! ```
+//!
+//! `press` and `release` take one or more keys. Without an `end`, the last
+//! keypad state is kept.
+
+use crate::frontend::Frontend;
+use crate::keymap::{self, Keymap};
+use crate::macros::Automation;
+use std::path::Path;
+
+/// Input of a frame.
+pub struct Input {
+    pub keypad: [bool; 16],
+    /// New speed in percent, if the source sets it.
+    pub speed: Option<u32>,
+}
+
+pub trait InputSource {
+    /// Input of a frame, or `None` once the source is over. Frames are read
+    /// in order.
+    fn read(&mut self, frame: u64, display: &mut dyn Frontend) -> Option<Input>;
+}
+
+/// Keys held down in the frontend, translated with a keymap.
+pub struct KeyboardInput {
+    pub keymap: Keymap,
+    automation: Automation,
+}
+
+impl KeyboardInput {
+    pub fn new(keymap: Keymap) -> KeyboardInput {
+        KeyboardInput {
+            keymap,
+            automation: Automation::new(),
+        }
+    }
+}
+
+impl InputSource for KeyboardInput {
+    fn read(&mut self, _frame: u64, display: &mut dyn Frontend) -> Option<Input> {
+        let keys = display.get_keys_down();
+        let mut keypad = self.keymap.keypad(&keys);
+        for (down, virtual_down) in keypad.iter_mut().zip(display.get_virtual_keys()) {
+            *down |= virtual_down;
+        }
+        self.automation.apply(&self.keymap, &keys, &mut keypad);
+
+        Some(Input {
+            keypad,
+            speed: None,
+        })
+    }
+}
+
+enum Action {
+    Press(Vec<usize>),
+    Release(Vec<usize>),
+    End,
+}
+
+/// Keys pressed and released by a script.
+pub struct ScriptInput {
+    actions: Vec<(u64, Action)>,
+    next: usize,
+    keypad: [bool; 16],
+}
+
+impl ScriptInput {
+    pub fn load(path: &Path) -> Result<ScriptInput, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| format!("cannot read script {}: {}", path.display(), error))?;
+        ScriptInput::parse(&text).map_err(|error| format!("{}: {}", path.display(), error))
+    }
+
+    pub fn parse(text: &str) -> Result<ScriptInput, String> {
+        let mut actions = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+
+            for statement in line.split(';').map(str::trim) {
+                if statement.is_empty() {
+                    continue;
+                }
+
+                let error = || format!("line {}: invalid statement: {}", number + 1, statement);
+                let (frame, action) = statement
+                    .strip_prefix("frame ")
+                    .and_then(|statement| statement.split_once(':'))
+                    .ok_or_else(error)?;
+                let frame: u64 = frame.trim().parse().map_err(|_| error())?;
+
+                let mut words = action.split_whitespace();
+                let name = words.next().ok_or_else(error)?;
+                let keys = words
+                    .map(|key| keymap::parse_chip8_key(key).ok_or_else(error))
+                    .collect::<Result<Vec<usize>, String>>()?;
+                let action = match name {
+                    "press" if !keys.is_empty() => Action::Press(keys),
+                    "release" if !keys.is_empty() => Action::Release(keys),
+                    "end" if keys.is_empty() => Action::End,
+                    _ => return Err(error()),
+                };
+
+                if actions.last().is_some_and(|(last, _)| *last > frame) {
+                    return Err(format!("line {}: frames must be in order", number + 1));
+                }
+                actions.push((frame, action));
+            }
+        }
+
+        Ok(ScriptInput {
+            actions,
+            next: 0,
+            keypad: [false; 16],
+        })
+    }
+}
+
+impl InputSource for ScriptInput {
+    fn read(&mut self, frame: u64, _display: &mut dyn Frontend) -> Option<Input> {
+        while let Some((action_frame, action)) = self.actions.get(self.next) {
+            if *action_frame > frame {
+                break;
+            }
+            match action {
+                Action::Press(keys) => keys.iter().for_each(|key| self.keypad[*key] = true),
+                Action::Release(keys) => keys.iter().for_each(|key| self.keypad[*key] = false),
+                Action::End => return None,
+            }
+            self.next += 1;
+        }
+
+        Some(Input {
+            keypad: self.keypad,
+            speed: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::Headless;
+
+    fn keys_down(input: Option<Input>) -> Option<Vec<usize>> {
+        input.map(|input| (0..16).filter(|key| input.keypad[*key]).collect())
+    }
+
+    #[test]
+    fn plays_scripts() {
+        let mut script = ScriptInput::parse(
+            "# Start the game, then hold 5 and A\n\
+             frame 1: press 1; frame 2: release 1\n\
+             \n\
+             frame 2: press 5 a\n\
+             frame 4: release 5\n\
+             frame 5: end",
+        )
+        .unwrap();
+
+        let frames: Vec<Option<Vec<usize>>> = (0..6)
+            .map(|frame| keys_down(script.read(frame, &mut Headless)))
+            .collect();
+        assert_eq!(
+            frames,
+            [
+                Some(vec![]),
+                Some(vec![1]),
+                Some(vec![5, 10]),
+                Some(vec![5, 10]),
+                Some(vec![10]),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_the_last_keys_without_end() {
+        let mut script = ScriptInput::parse("frame 0: press 3").unwrap();
+
+        assert_eq!(keys_down(script.read(1000, &mut Headless)), Some(vec![3]));
+    }
+
+    #[test]
+    fn rejects_invalid_statements() {
+        let error = |text| ScriptInput::parse(text).err().unwrap();
+
+        assert_eq!(
+            error("frame 1 press 1"),
+            "line 1: invalid statement: frame 1 press 1"
+        );
+        assert_eq!(
+            error("frame x: press 1"),
+            "line 1: invalid statement: frame x: press 1"
+        );
+        assert_eq!(
+            error("\nframe 1: press"),
+            "line 2: invalid statement: frame 1: press"
+        );
+        assert_eq!(
+            error("frame 1: press G"),
+            "line 1: invalid statement: frame 1: press G"
+        );
+        assert_eq!(
+            error("frame 1: end 1"),
+            "line 1: invalid statement: frame 1: end 1"
+        );
+        assert_eq!(
+            error("frame 2: press 1; frame 1: release 1"),
+            "line 1: frames must be in order"
+        );
+    }
+}
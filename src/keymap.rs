@@ -0,0 +1,324 @@
+//! # Keymap
+//!
+//! Mapping from the host keyboard to the 16 keys of the Chip8 keypad. Each
+//! Chip8 key can be bound to several host keys.
+//!
+//! A keymap file contains one line per Chip8 key, the key as an hexadecimal
+//! digit followed by the host keys, keys which are not listed keep their
+//! default binding:
+//!
+//! ```text
+//! # Chip8 key = host keys
+//! 5 = W, Up
+//! 8 = S, Down
+//! ```
+//!
+//! It can also contain turbos and macros, see [`crate::macros`].
+
+use crate::macros::{Macro, Turbo};
+use minifb::Key;
+use std::fmt;
+use std::path::Path;
+
+/// Default binding, the conventional layout of the keypad on the left of a
+/// QWERTY keyboard:
+///
+/// ```text
+/// 1 2 3 C      1 2 3 4
+/// 4 5 6 D  ->  Q W E R
+/// 7 8 9 E      A S D F
+/// A 0 B F      Z X C V
+/// ```
+const DEFAULT_KEYS: [Key; 16] = [
+    Key::X,
+    Key::Key1,
+    Key::Key2,
+    Key::Key3,
+    Key::Q,
+    Key::W,
+    Key::E,
+    Key::A,
+    Key::S,
+    Key::D,
+    Key::Z,
+    Key::C,
+    Key::Key4,
+    Key::R,
+    Key::F,
+    Key::V,
+];
+
+/// Host keys which can be bound, named after their variant in a keymap file.
+const HOST_KEYS: [Key; 106] = [
+    Key::Key0,
+    Key::Key1,
+    Key::Key2,
+    Key::Key3,
+    Key::Key4,
+    Key::Key5,
+    Key::Key6,
+    Key::Key7,
+    Key::Key8,
+    Key::Key9,
+    Key::A,
+    Key::B,
+    Key::C,
+    Key::D,
+    Key::E,
+    Key::F,
+    Key::G,
+    Key::H,
+    Key::I,
+    Key::J,
+    Key::K,
+    Key::L,
+    Key::M,
+    Key::N,
+    Key::O,
+    Key::P,
+    Key::Q,
+    Key::R,
+    Key::S,
+    Key::T,
+    Key::U,
+    Key::V,
+    Key::W,
+    Key::X,
+    Key::Y,
+    Key::Z,
+    Key::F1,
+    Key::F2,
+    Key::F3,
+    Key::F4,
+    Key::F5,
+    Key::F6,
+    Key::F7,
+    Key::F8,
+    Key::F9,
+    Key::F10,
+    Key::F11,
+    Key::F12,
+    Key::F13,
+    Key::F14,
+    Key::F15,
+    Key::Down,
+    Key::Left,
+    Key::Right,
+    Key::Up,
+    Key::Apostrophe,
+    Key::Backquote,
+    Key::Backslash,
+    Key::Comma,
+    Key::Equal,
+    Key::LeftBracket,
+    Key::Minus,
+    Key::Period,
+    Key::RightBracket,
+    Key::Semicolon,
+    Key::Slash,
+    Key::Backspace,
+    Key::Delete,
+    Key::End,
+    Key::Enter,
+    Key::Escape,
+    Key::Home,
+    Key::Insert,
+    Key::Menu,
+    Key::PageDown,
+    Key::PageUp,
+    Key::Pause,
+    Key::Space,
+    Key::Tab,
+    Key::NumLock,
+    Key::CapsLock,
+    Key::ScrollLock,
+    Key::LeftShift,
+    Key::RightShift,
+    Key::LeftCtrl,
+    Key::RightCtrl,
+    Key::NumPad0,
+    Key::NumPad1,
+    Key::NumPad2,
+    Key::NumPad3,
+    Key::NumPad4,
+    Key::NumPad5,
+    Key::NumPad6,
+    Key::NumPad7,
+    Key::NumPad8,
+    Key::NumPad9,
+    Key::NumPadDot,
+    Key::NumPadSlash,
+    Key::NumPadAsterisk,
+    Key::NumPadMinus,
+    Key::NumPadPlus,
+    Key::NumPadEnter,
+    Key::LeftAlt,
+    Key::RightAlt,
+    Key::LeftSuper,
+    Key::RightSuper,
+];
+
+pub struct Keymap {
+    keys: [Vec<Key>; 16],
+    pub turbos: Vec<Turbo>,
+    pub macros: Vec<Macro>,
+}
+
+impl Keymap {
+    pub fn new() -> Keymap {
+        Keymap {
+            keys: DEFAULT_KEYS.map(|key| vec![key]),
+            turbos: Vec::new(),
+            macros: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Keymap, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| format!("cannot read keymap {}: {}", path.display(), error))?;
+        Keymap::parse(&text).map_err(|error| format!("{}: {}", path.display(), error))
+    }
+
+    pub fn parse(text: &str) -> Result<Keymap, String> {
+        let mut keymap = Keymap::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let error = |message: String| format!("line {}: {}", number + 1, message);
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected <chip8 key> = <host keys>".to_string()))?;
+            let name = name.trim();
+            let host_key = |name: &str| {
+                let name = name.trim();
+                parse_key(name).ok_or_else(|| error(format!("unknown key: {}", name)))
+            };
+
+            if let Some(host_name) = name.strip_prefix("turbo ") {
+                let turbo = Turbo::parse(host_key(host_name)?, value).map_err(error)?;
+                keymap.turbos.push(turbo);
+            } else if let Some(host_name) = name.strip_prefix("macro ") {
+                let macro_keys = Macro::parse(host_key(host_name)?, value).map_err(error)?;
+                keymap.macros.push(macro_keys);
+            } else {
+                let chip8_key = parse_chip8_key(name)
+                    .ok_or_else(|| error(format!("invalid chip8 key: {}", name)))?;
+                keymap.keys[chip8_key] = value
+                    .split(',')
+                    .filter(|name| !name.trim().is_empty())
+                    .map(host_key)
+                    .collect::<Result<_, _>>()?;
+            }
+        }
+
+        Ok(keymap)
+    }
+
+    /// State of the Chip8 keypad when `host_keys` are held down.
+    pub fn keypad(&self, host_keys: &[Key]) -> [bool; 16] {
+        std::array::from_fn(|chip8_key| {
+            self.keys[chip8_key]
+                .iter()
+                .any(|key| host_keys.contains(key))
+        })
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        Keymap::new()
+    }
+}
+
+/// Keymap file binding every Chip8 key.
+impl fmt::Display for Keymap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (chip8_key, host_keys) in self.keys.iter().enumerate() {
+            let names: Vec<String> = host_keys.iter().map(|key| format!("{:?}", key)).collect();
+            let line = format!("{:X} = {}", chip8_key, names.join(", "));
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        for turbo in &self.turbos {
+            writeln!(f, "{}", turbo)?;
+        }
+        for macro_keys in &self.macros {
+            writeln!(f, "{}", macro_keys)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse a Chip8 key written as an hexadecimal digit.
+pub fn parse_chip8_key(text: &str) -> Option<usize> {
+    u8::from_str_radix(text, 16)
+        .ok()
+        .filter(|key| *key < 16)
+        .map(usize::from)
+}
+
+/// Find a host key by name, case insensitive. Digits can be written without
+/// the `Key` prefix.
+pub fn parse_key(name: &str) -> Option<Key> {
+    HOST_KEYS.iter().copied().find(|key| {
+        let key_name = format!("{:?}", key);
+        key_name.eq_ignore_ascii_case(name)
+            || (key_name.starts_with("Key") && key_name[3..] == *name)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binds_the_listed_keys() {
+        let keymap = Keymap::parse("# Arrows\n5 = W, Up\n\n8 = s, Down # move down\n").unwrap();
+
+        assert!(keymap.keypad(&[Key::Up])[5]);
+        assert!(keymap.keypad(&[Key::S])[8]);
+        // Keys which are not listed keep their default binding
+        assert!(keymap.keypad(&[Key::X])[0]);
+        assert!(!keymap.keypad(&[Key::Up])[8]);
+    }
+
+    #[test]
+    fn unbinds_keys_without_host_keys() {
+        let keymap = Keymap::parse("0 =").unwrap();
+
+        assert_eq!(keymap.keypad(&[Key::X]), [false; 16]);
+    }
+
+    #[test]
+    fn reports_the_line_of_errors() {
+        let error = |text| Keymap::parse(text).err().unwrap();
+
+        assert_eq!(error("\n5 W"), "line 2: expected <chip8 key> = <host keys>");
+        assert_eq!(error("5 = Nope"), "line 1: unknown key: Nope");
+        assert_eq!(error("G = W"), "line 1: invalid chip8 key: G");
+        assert_eq!(error("10 = W"), "line 1: invalid chip8 key: 10");
+    }
+
+    #[test]
+    fn writes_the_keymap_it_reads() {
+        let keymap = Keymap::parse("5 = W, Up").unwrap();
+        let text = keymap.to_string();
+
+        assert!(text.contains("5 = W, Up\n"));
+        assert_eq!(Keymap::parse(&text).unwrap().to_string(), text);
+    }
+
+    #[test]
+    fn finds_keys_by_name() {
+        assert_eq!(parse_key("space"), Some(Key::Space));
+        assert_eq!(parse_key("7"), Some(Key::Key7));
+        assert_eq!(parse_key("Key7"), Some(Key::Key7));
+        assert_eq!(parse_key("Nope"), None);
+        assert_eq!(parse_chip8_key("f"), Some(15));
+        assert_eq!(parse_chip8_key("10"), None);
+    }
+}
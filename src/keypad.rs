@@ -0,0 +1,185 @@
+//! # Keypad
+//!
+//! Virtual Chip8 keypad drawn next to the screen in the window, to play with
+//! the mouse or a touch screen. It also shows the keys held down and the keys
+//! the program reads with EX9E and EXA1.
+
+use crate::font::{self, Canvas};
+
+/// Keys in the order of the COSMAC VIP keypad.
+const LAYOUT: [[usize; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+// Programs do not read every key on every frame, so a key stays highlighted
+// for a few frames after it was read
+const POLL_FRAMES: u32 = 15;
+
+const KEY_COLOR: u32 = 0x303030;
+const POLLED_COLOR: u32 = 0x204060;
+const DOWN_COLOR: u32 = 0xC0C0C0;
+const TEXT_COLOR: u32 = 0xFFFFFF;
+const DOWN_TEXT_COLOR: u32 = 0x000000;
+
+// Window pixels between two keys
+const GAP: usize = 2;
+
+/// Square area of the window the keypad is drawn into.
+pub struct Area {
+    pub x: usize,
+    pub y: usize,
+    pub size: usize,
+}
+
+pub struct Keypad {
+    keys: [bool; 16],
+    // Frames since each key was last read
+    polled: [u32; 16],
+    // Whether the keypad looks different since it was last drawn
+    changed: bool,
+}
+
+impl Keypad {
+    pub fn new() -> Keypad {
+        Keypad {
+            keys: [false; 16],
+            polled: [POLL_FRAMES; 16],
+            changed: true,
+        }
+    }
+
+    /// Show the keys held down and the keys read during the last frame.
+    pub fn update(&mut self, keys: [bool; 16], polled: [bool; 16]) {
+        let before: [(u32, u32); 16] = std::array::from_fn(|key| self.colors(key));
+
+        self.keys = keys;
+        for (frames, polled) in self.polled.iter_mut().zip(polled) {
+            *frames = if polled {
+                0
+            } else {
+                (*frames + 1).min(POLL_FRAMES)
+            };
+        }
+
+        self.changed |= (0..16).any(|key| self.colors(key) != before[key]);
+    }
+
+    /// Whether the keypad changed since the last call, to only redraw it then.
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+
+    /// Background and text colors of a key.
+    fn colors(&self, key: usize) -> (u32, u32) {
+        if self.keys[key] {
+            (DOWN_COLOR, DOWN_TEXT_COLOR)
+        } else if self.polled[key] < POLL_FRAMES {
+            (POLLED_COLOR, TEXT_COLOR)
+        } else {
+            (KEY_COLOR, TEXT_COLOR)
+        }
+    }
+
+    /// Key under the window position `(x, y)`.
+    pub fn key_at(&self, area: &Area, x: usize, y: usize) -> Option<usize> {
+        if x < area.x || y < area.y {
+            return None;
+        }
+        let column = (x - area.x) * 4 / area.size.max(1);
+        let row = (y - area.y) * 4 / area.size.max(1);
+
+        LAYOUT.get(row)?.get(column).copied()
+    }
+
+    pub fn draw(&self, canvas: &mut Canvas, area: &Area) {
+        let cell = area.size / 4;
+        let scale = (cell / (3 * font::GLYPH_HEIGHT)).max(1);
+
+        for (row, keys) in LAYOUT.iter().enumerate() {
+            for (column, key) in keys.iter().enumerate() {
+                let (background, text) = self.colors(*key);
+
+                let x = area.x + column * cell;
+                let y = area.y + row * cell;
+                let size = cell.saturating_sub(GAP);
+                canvas.fill_rect(x, y, size, size, background);
+
+                let label = format!("{:X}", key);
+                let (text_width, text_height) = font::text_size(&label, scale);
+                let text_x = x + size.saturating_sub(text_width) / 2;
+                let text_y = y + size.saturating_sub(text_height) / 2;
+                canvas.draw_text(text_x, text_y, &label, scale, text);
+            }
+        }
+    }
+}
+
+impl Default for Keypad {
+    fn default() -> Keypad {
+        Keypad::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Keys of 20x20 window pixels
+    const AREA: Area = Area {
+        x: 100,
+        y: 20,
+        size: 80,
+    };
+
+    #[test]
+    fn finds_the_key_under_a_position() {
+        let keypad = Keypad::new();
+
+        assert_eq!(keypad.key_at(&AREA, 100, 20), Some(0x1));
+        assert_eq!(keypad.key_at(&AREA, 119, 39), Some(0x1));
+        assert_eq!(keypad.key_at(&AREA, 120, 40), Some(0x5));
+        assert_eq!(keypad.key_at(&AREA, 179, 20), Some(0xC));
+        assert_eq!(keypad.key_at(&AREA, 120, 99), Some(0x0));
+        assert_eq!(keypad.key_at(&AREA, 179, 99), Some(0xF));
+    }
+
+    #[test]
+    fn finds_no_key_outside_of_the_keypad() {
+        let keypad = Keypad::new();
+
+        assert_eq!(keypad.key_at(&AREA, 99, 50), None);
+        assert_eq!(keypad.key_at(&AREA, 150, 19), None);
+        assert_eq!(keypad.key_at(&AREA, 180, 50), None);
+        assert_eq!(keypad.key_at(&AREA, 150, 100), None);
+    }
+
+    #[test]
+    fn only_changes_when_the_keys_look_different() {
+        let mut keypad = Keypad::new();
+        assert!(keypad.take_changed());
+        assert!(!keypad.take_changed());
+
+        let mut polled = [false; 16];
+        polled[5] = true;
+        keypad.update([false; 16], polled);
+        assert!(keypad.take_changed());
+
+        // The key stays highlighted until it was not read for a while
+        for _ in 1..POLL_FRAMES {
+            keypad.update([false; 16], [false; 16]);
+            assert!(!keypad.take_changed());
+        }
+        keypad.update([false; 16], [false; 16]);
+        assert!(keypad.take_changed());
+
+        let mut keys = [false; 16];
+        keys[0xA] = true;
+        keypad.update(keys, [false; 16]);
+        assert!(keypad.take_changed());
+        keypad.update(keys, [false; 16]);
+        assert!(!keypad.take_changed());
+    }
+}
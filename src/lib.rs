@@ -0,0 +1,31 @@
+//! # Chip8 emulator
+//!
+//! Library of the Chip8 emulator, shared by the emulator and the tools
+//! working on Chip8 programs.
+
+pub mod asm;
+pub mod coverage;
+pub mod cpu;
+pub mod crt;
+pub mod debugger;
+pub mod disasm;
+pub mod font;
+pub mod frontend;
+pub mod gdb;
+pub mod graphics;
+pub mod input;
+pub mod keymap;
+pub mod keypad;
+pub mod macros;
+pub mod monitor;
+pub mod movie;
+pub mod options;
+pub mod osd;
+pub mod pixel;
+pub mod profile;
+pub mod profiler;
+pub mod quirks;
+pub mod symbols;
+pub mod terminal;
+pub mod trace;
+pub mod views;
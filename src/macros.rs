@@ -0,0 +1,253 @@
+//! # Macros
+//!
+//! Host keys which press Chip8 keys on their own, configured in the keymap
+//! file:
+//!
+//! ```text
+//! # Press and release the key 5 every 4 frames while Space is held
+//! turbo Space = 5:4
+//! # Hold 4 for 10 frames, nothing for 2 frames, then 4 and 5 for 10 frames
+//! macro M = 4:10, -:2, 4+5:10
+//! ```
+//!
+//! They only change the keypad state computed from the host keys, which is
+//! what movies record, so runs using them replay like any other.
+
+use crate::keymap::{self, Keymap};
+use minifb::Key;
+use std::fmt;
+
+/// Toggle a Chip8 key while a host key is held.
+pub struct Turbo {
+    pub host_key: Key,
+    pub chip8_key: usize,
+    /// Frames the key stays down, then up.
+    pub period: u32,
+}
+
+/// Sequence of keypad states played when a host key is pressed.
+pub struct Macro {
+    pub host_key: Key,
+    /// Keys held down and for how many frames.
+    pub steps: Vec<([bool; 16], u32)>,
+}
+
+impl Turbo {
+    /// Parse `<chip8 key>:<frames>`.
+    pub fn parse(host_key: Key, text: &str) -> Result<Turbo, String> {
+        let (chip8_key, period) = text
+            .trim()
+            .split_once(':')
+            .ok_or("expected <chip8 key>:<frames>")?;
+
+        Ok(Turbo {
+            host_key,
+            chip8_key: parse_chip8_key(chip8_key)?,
+            period: parse_frames(period)?,
+        })
+    }
+}
+
+impl Macro {
+    /// Parse the steps `<chip8 keys>:<frames>, ...`, the keys being joined
+    /// with `+`, or `-` for none.
+    pub fn parse(host_key: Key, text: &str) -> Result<Macro, String> {
+        let mut steps = Vec::new();
+
+        for step in text.split(',') {
+            let (keys, frames) = step
+                .trim()
+                .split_once(':')
+                .ok_or("expected <chip8 keys>:<frames>")?;
+
+            let mut keypad = [false; 16];
+            if keys != "-" {
+                for key in keys.split('+') {
+                    keypad[parse_chip8_key(key)?] = true;
+                }
+            }
+            steps.push((keypad, parse_frames(frames)?));
+        }
+
+        Ok(Macro { host_key, steps })
+    }
+
+    fn length(&self) -> u32 {
+        self.steps.iter().map(|(_, frames)| frames).sum()
+    }
+
+    /// Keypad state `frame` frames after the start of the macro.
+    fn keypad(&self, mut frame: u32) -> [bool; 16] {
+        for (keypad, frames) in &self.steps {
+            if frame < *frames {
+                return *keypad;
+            }
+            frame -= frames;
+        }
+
+        [false; 16]
+    }
+}
+
+impl fmt::Display for Turbo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "turbo {:?} = {:X}:{}",
+            self.host_key, self.chip8_key, self.period
+        )
+    }
+}
+
+impl fmt::Display for Macro {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let steps: Vec<String> = self
+            .steps
+            .iter()
+            .map(|(keypad, frames)| {
+                let keys: Vec<String> = (0..16)
+                    .filter(|key| keypad[*key])
+                    .map(|key| format!("{:X}", key))
+                    .collect();
+                let keys = if keys.is_empty() {
+                    "-".to_string()
+                } else {
+                    keys.join("+")
+                };
+                format!("{}:{}", keys, frames)
+            })
+            .collect();
+
+        write!(f, "macro {:?} = {}", self.host_key, steps.join(", "))
+    }
+}
+
+/// Progress of the turbos and macros of a keymap.
+pub struct Automation {
+    // Frames each turbo host key has been held for
+    turbos: Vec<Option<u32>>,
+    // Frames since each macro started, while it plays
+    macros: Vec<Option<u32>>,
+    previous_keys: Vec<Key>,
+}
+
+impl Automation {
+    pub fn new() -> Automation {
+        Automation {
+            turbos: Vec::new(),
+            macros: Vec::new(),
+            previous_keys: Vec::new(),
+        }
+    }
+
+    /// Add the keys pressed by the turbos and macros during this frame to
+    /// `keypad`, `host_keys` being the host keys held down.
+    pub fn apply(&mut self, keymap: &Keymap, host_keys: &[Key], keypad: &mut [bool; 16]) {
+        self.turbos.resize(keymap.turbos.len(), None);
+        self.macros.resize(keymap.macros.len(), None);
+
+        for (turbo, held) in keymap.turbos.iter().zip(&mut self.turbos) {
+            *held = if host_keys.contains(&turbo.host_key) {
+                Some(held.map_or(0, |frames| frames + 1))
+            } else {
+                None
+            };
+
+            if let Some(frames) = held {
+                keypad[turbo.chip8_key] |= (*frames / turbo.period) % 2 == 0;
+            }
+        }
+
+        for (macro_keys, playing) in keymap.macros.iter().zip(&mut self.macros) {
+            // Pressing the host key again restarts the macro
+            let pressed = host_keys.contains(&macro_keys.host_key)
+                && !self.previous_keys.contains(&macro_keys.host_key);
+            *playing = match playing {
+                _ if pressed => Some(0),
+                Some(frame) if *frame + 1 < macro_keys.length() => Some(*frame + 1),
+                _ => None,
+            };
+
+            if let Some(frame) = playing {
+                let steps = macro_keys.keypad(*frame);
+                for (down, step_down) in keypad.iter_mut().zip(steps) {
+                    *down |= step_down;
+                }
+            }
+        }
+
+        self.previous_keys = host_keys.to_vec();
+    }
+}
+
+impl Default for Automation {
+    fn default() -> Automation {
+        Automation::new()
+    }
+}
+
+fn parse_chip8_key(text: &str) -> Result<usize, String> {
+    keymap::parse_chip8_key(text.trim()).ok_or(format!("invalid chip8 key: {}", text.trim()))
+}
+
+fn parse_frames(text: &str) -> Result<u32, String> {
+    match text.trim().parse() {
+        Ok(frames) if frames > 0 => Ok(frames),
+        _ => Err(format!("invalid number of frames: {}", text.trim())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_turbos_and_macros() {
+        let keymap = Keymap::parse("turbo Space = 5:4\nmacro M = 4:10, -:2, 4+5:10").unwrap();
+
+        let turbo = &keymap.turbos[0];
+        assert_eq!(
+            (turbo.host_key, turbo.chip8_key, turbo.period),
+            (Key::Space, 5, 4)
+        );
+        let macro_keys = &keymap.macros[0];
+        assert_eq!(macro_keys.host_key, Key::M);
+        assert_eq!(macro_keys.length(), 22);
+        assert!(macro_keys.keypad(9)[4] && !macro_keys.keypad(9)[5]);
+        assert_eq!(macro_keys.keypad(10), [false; 16]);
+        assert!(macro_keys.keypad(12)[4] && macro_keys.keypad(12)[5]);
+
+        assert_eq!(turbo.to_string(), "turbo Space = 5:4");
+        assert_eq!(macro_keys.to_string(), "macro M = 4:10, -:2, 4+5:10");
+    }
+
+    #[test]
+    fn rejects_invalid_steps() {
+        let error = |text| Keymap::parse(text).err().unwrap();
+
+        assert_eq!(
+            error("turbo Space = 5"),
+            "line 1: expected <chip8 key>:<frames>"
+        );
+        assert_eq!(error("turbo Space = G:4"), "line 1: invalid chip8 key: G");
+        assert_eq!(
+            error("macro M = 4:0"),
+            "line 1: invalid number of frames: 0"
+        );
+        assert_eq!(error("macro Nope = 4:1"), "line 1: unknown key: Nope");
+    }
+
+    #[test]
+    fn toggles_turbo_keys() {
+        let keymap = Keymap::parse("turbo Space = 5:2").unwrap();
+        let mut automation = Automation::new();
+
+        let mut presses = Vec::new();
+        for _ in 0..6 {
+            let mut keypad = [false; 16];
+            automation.apply(&keymap, &[Key::Space], &mut keypad);
+            presses.push(keypad[5]);
+        }
+        assert_eq!(presses, [true, true, false, false, true, true]);
+    }
+}
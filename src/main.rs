@@ -2,91 +2,352 @@
 //!
 //! This is a simple Chip8 emulator written in Rust.
 
-use pixel::Pixel;
-
-mod cpu;
-mod pixel;
+use chip8_emulator::coverage::Coverage;
+use chip8_emulator::cpu::CPU;
+use chip8_emulator::debugger::Debugger;
+use chip8_emulator::frontend::{Frontend, Headless};
+use chip8_emulator::gdb::GdbStub;
+use chip8_emulator::input::{InputSource, KeyboardInput};
+use chip8_emulator::keymap::Keymap;
+use chip8_emulator::monitor::Monitor;
+use chip8_emulator::movie::{Header, Recorder};
+use chip8_emulator::options::{self, Options};
+use chip8_emulator::osd::Osd;
+use chip8_emulator::pixel::{PixelDisplay, Screen};
+use chip8_emulator::profile::Profiles;
+use chip8_emulator::profiler::Profiler;
+use chip8_emulator::symbols::Symbols;
+use chip8_emulator::terminal::TerminalDisplay;
+use chip8_emulator::trace::Trace;
+use chip8_emulator::views::Views;
+use minifb::Key;
+use std::path::Path;
 
 const CPU_SPEED: u32 = 4; // 4 instructions per cycle
+const SPEED_STEP: u32 = 25; // Speed change in percent
+const MAX_SPEED: u32 = 400;
 
 fn main() {
-    // get rom path from command line
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: chip8 <rom_path>");
-        return;
+    // get rom path and options from command line
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut options = match Options::parse(&args) {
+        Ok(options) => options,
+        Err(error) => {
+            println!("{}", error);
+            println!("{}", options::USAGE);
+            return;
+        }
+    };
+    let rom_path = &options.rom_path;
+
+    let mut cpu = CPU::new();
+    cpu.quirks = options.quirks;
+    let start_emulation = cpu.load_rom(rom_path);
+    let mut speed = 100;
+    let mut seed = options.seed.unwrap_or_else(rand::random);
+
+    // The keymap given on the command line wins over the profile of the ROM,
+    // which is then not even loaded. A profile which cannot be read is
+    // ignored, it can be overwritten by saving another one.
+    let profiles = Profiles::new(options.profiles.as_deref());
+    let mut invalid_profile = false;
+    let profile = match options.keymap {
+        Some(_) => None,
+        None => match profiles.load(&cpu.rom_hash) {
+            Some(Ok(keymap)) => Some(keymap),
+            Some(Err(error)) => {
+                println!("ignoring the profile: {}", error);
+                invalid_profile = true;
+                None
+            }
+            None => None,
+        },
+    };
+    let has_profile = profile.is_some();
+    let keymap = options
+        .keymap
+        .take()
+        .or(profile)
+        .unwrap_or_else(Keymap::new);
+    let mut keyboard = KeyboardInput::new(keymap);
+
+    // A movie replays the run with the settings it was recorded with
+    let mut playback: Option<Box<dyn InputSource>> = None;
+    if let Some(movie) = options.play_input.take() {
+        if movie.header.rom_hash != cpu.rom_hash {
+            println!("the movie was recorded with another ROM");
+            return;
+        }
+        seed = movie.header.seed;
+        speed = movie.header.speed;
+        cpu.quirks = movie.header.quirks;
+        playback = Some(Box::new(movie));
+    }
+    if let Some(script) = options.input_script.take() {
+        playback = Some(Box::new(script));
     }
-    let rom_path = &args[1];
+    cpu.set_seed(seed);
 
-    let mut cpu = cpu::CPU::new();
-    let mut display = pixel::PixelDisplay::new();
-    let mut pixels = Pixel::init();
+    let symbols = match Symbols::for_rom(rom_path, options.symbols.as_deref()) {
+        Ok(symbols) => symbols,
+        Err(error) => {
+            println!("{}", error);
+            return;
+        }
+    };
 
-    let mut continue_emulation = true;
+    let mut recorder = match &options.record_input {
+        Some(path) => {
+            let header = Header {
+                rom_hash: cpu.rom_hash.clone(),
+                seed,
+                speed,
+                quirks: cpu.quirks,
+            };
+            match Recorder::create(path, &header) {
+                Ok(recorder) => Some(recorder),
+                Err(error) => {
+                    println!("{}", error);
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+
+    let mut monitors: Vec<Box<dyn Monitor>> = Vec::new();
+    if let Some(port) = options.gdb {
+        match GdbStub::listen(port) {
+            Ok(gdb) => monitors.push(Box::new(gdb)),
+            Err(error) => {
+                println!("{}", error);
+                return;
+            }
+        }
+    }
+
+    let mut display: Box<dyn Frontend> = match options.terminal {
+        _ if options.headless => Box::new(Headless),
+        Some(mode) => match TerminalDisplay::new(mode, options.scale, options.palette) {
+            Ok(display) => Box::new(display),
+            Err(error) => {
+                println!("{}", error);
+                return;
+            }
+        },
+        None => Box::new(PixelDisplay::new(
+            options.scale,
+            options.scaling,
+            options.crt,
+            options.keypad,
+            options.fullscreen,
+        )),
+    };
+    let mut screen = Screen::new();
+    if options.views {
+        match Views::open(symbols.clone()) {
+            Ok(views) => monitors.push(Box::new(views)),
+            Err(error) => {
+                println!("{}", error);
+                return;
+            }
+        }
+    }
 
-    let start_emulation = cpu.load_rom(&rom_path);
+    let rom_name = Path::new(rom_path)
+        .file_name()
+        .map_or(rom_path.clone(), |name| name.to_string_lossy().to_string());
+    let mut osd = Osd::new(&rom_name);
+    if has_profile {
+        osd.message("Profile loaded");
+    }
+    if invalid_profile {
+        osd.message("Invalid profile ignored");
+    }
+    let mut paused = false;
+    // Frames emulated, pauses excluded
+    let mut frame = 0;
+    // Budget and instructions executed of the frame being emulated. A
+    // debugger can stop it in the middle, the timers, the input and the frame
+    // counter only advancing once its budget is used
+    let mut current_frame: Option<(u32, u32)> = None;
+
+    let mut continue_emulation = true;
+    if options.debug {
+        monitors.push(Box::new(Debugger::new(&cpu, symbols.clone())));
+    }
+    // Traced, covered and profiled after the debuggers, which can keep the
+    // instructions from being executed
+    if let Some(path) = &options.trace {
+        match Trace::create(
+            path,
+            options.trace_range,
+            options.trace_frames,
+            symbols.clone(),
+        ) {
+            Ok(trace) => monitors.push(Box::new(trace)),
+            Err(error) => {
+                println!("{}", error);
+                return;
+            }
+        }
+    }
+    if options.coverage.is_some() || options.heatmap.is_some() {
+        let rom_size = std::fs::metadata(rom_path).map_or(0, |metadata| metadata.len() as usize);
+        match Coverage::create(
+            options.coverage.as_deref(),
+            options.heatmap.as_deref(),
+            rom_size,
+        ) {
+            Ok(coverage) => monitors.push(Box::new(coverage)),
+            Err(error) => {
+                println!("{}", error);
+                return;
+            }
+        }
+    }
+    if options.profiler {
+        monitors.push(Box::new(Profiler::new(symbols.clone())));
+    }
 
     if start_emulation {
         while continue_emulation {
-            continue_emulation = listen(&mut cpu, &mut display);
+            continue_emulation = display.is_open() && !display.is_key_pressed(Key::Escape);
+            shortcuts(display.as_mut(), &mut osd, &mut paused, &mut speed);
+
+            if display.is_key_pressed(Key::F5) {
+                match profiles.save(&cpu.rom_hash, &keyboard.keymap) {
+                    Ok(()) => osd.message("Profile saved"),
+                    Err(error) => osd.message(&error),
+                }
+            }
 
-            for _ in 0..CPU_SPEED {
-                cpu.execute_opcode(cpu.get_opcode(), &mut display, &mut pixels);
+            for monitor in &mut monitors {
+                monitor.poll(&mut cpu);
+            }
+            if monitors.iter().any(|monitor| monitor.is_over()) {
+                break;
             }
+            // The emulation does not advance while paused or in a debugger
+            let halted = paused || monitors.iter().any(|monitor| monitor.is_stopped());
+
+            if !halted && current_frame.is_none() {
+                // The keyboard is always read so that it is up to date when a
+                // movie or script is over
+                let mut input = keyboard.read(frame, display.as_mut());
+                match playback
+                    .as_mut()
+                    .map(|source| source.read(frame, display.as_mut()))
+                {
+                    Some(Some(played)) => input = Some(played),
+                    Some(None) => {
+                        playback = None;
+                        osd.message("End of the input");
+                        if options.headless && options.frames.is_none() {
+                            break;
+                        }
+                    }
+                    None => {}
+                }
+
+                if let Some(input) = input {
+                    if let Some(input_speed) = input.speed {
+                        speed = input_speed;
+                    }
+                    if let Some(recorder) = &mut recorder {
+                        recorder.record(frame, input.keypad, speed);
+                    }
+                    cpu.set_keys(input.keypad);
+                }
 
-            display.draw(&pixels);
-            cpu.count_down();
+                let budget = (CPU_SPEED * speed / 100).max(1);
+                for monitor in &mut monitors {
+                    monitor.start_frame(frame, budget);
+                }
+                current_frame = Some((budget, 0));
+            }
+
+            // A CPU waiting for the vertical blank stops using the budget
+            let mut executed = 0;
+            if let Some((budget, instructions)) = current_frame.as_mut().filter(|_| !halted) {
+                while *instructions < *budget && !cpu.waiting_vblank {
+                    if !monitors
+                        .iter_mut()
+                        .all(|monitor| monitor.before_instruction(&cpu))
+                    {
+                        break;
+                    }
+                    cpu.execute_opcode(cpu.get_opcode(), &mut screen);
+                    *instructions += 1;
+                    executed += 1;
+                    for monitor in &mut monitors {
+                        monitor.after_instruction(&cpu);
+                    }
+                }
+            }
+
+            if current_frame
+                .is_some_and(|(budget, instructions)| instructions >= budget || cpu.waiting_vblank)
+            {
+                current_frame = None;
+                for monitor in &mut monitors {
+                    monitor.end_frame(&cpu);
+                }
+                cpu.count_down();
+                frame += 1;
+            }
+
+            for monitor in &mut monitors {
+                monitor.draw(&cpu);
+            }
+            osd.frame(executed);
+            display.show_keypad(cpu.key, cpu.key_polled);
+            display.draw(&screen, &osd);
+            screen.presented();
+            if options.frames.is_some_and(|frames| frame >= frames) {
+                continue_emulation = false;
+            }
         }
     }
+
+    if let Some(recorder) = &mut recorder {
+        recorder.finish(frame);
+    }
+    for monitor in &mut monitors {
+        monitor.finish(&cpu);
+    }
 }
 
-fn listen(cpu: &mut cpu::CPU, screen: &mut pixel::PixelDisplay) -> bool {
-    let mut continue_emulation = true;
+/// Handle the emulator shortcuts: `P` pauses, `F1` shows the counters, `F2`
+/// toggles the messages, `F3` and `F4` decrease and increase the speed, `F11`
+/// toggles the fullscreen window.
+fn shortcuts(display: &mut dyn Frontend, osd: &mut Osd, paused: &mut bool, speed: &mut u32) {
+    if display.is_key_pressed(Key::P) {
+        *paused = !*paused;
+        osd.set_paused(*paused);
+    }
 
-    if let Some(key) = screen.get_key_down() {
-        match key {
-            minifb::Key::Escape => continue_emulation = false,
-            minifb::Key::NumPad7 => cpu.key[0] = true,
-            minifb::Key::NumPad8 => cpu.key[1] = true,
-            minifb::Key::NumPad9 => cpu.key[2] = true,
-            minifb::Key::NumPadAsterisk => cpu.key[3] = true,
-            minifb::Key::NumPad4 => cpu.key[4] = true,
-            minifb::Key::NumPad5 => cpu.key[5] = true,
-            minifb::Key::NumPad6 => cpu.key[6] = true,
-            minifb::Key::NumPadMinus => cpu.key[7] = true,
-            minifb::Key::NumPad1 => cpu.key[8] = true,
-            minifb::Key::NumPad2 => cpu.key[9] = true,
-            minifb::Key::NumPad3 => cpu.key[10] = true,
-            minifb::Key::NumPadPlus => cpu.key[11] = true,
-            minifb::Key::Right => cpu.key[12] = true,
-            minifb::Key::NumPad0 => cpu.key[13] = true,
-            minifb::Key::NumPadDot => cpu.key[14] = true,
-            minifb::Key::NumPadEnter => cpu.key[15] = true,
-            _ => {}
-        }
+    if display.is_key_pressed(Key::F1) {
+        osd.toggle_stats();
     }
 
-    if let Some(key) = screen.get_key_up() {
-        match key {
-            minifb::Key::NumPad7 => cpu.key[0] = false,
-            minifb::Key::NumPad8 => cpu.key[1] = false,
-            minifb::Key::NumPad9 => cpu.key[2] = false,
-            minifb::Key::NumPadAsterisk => cpu.key[3] = false,
-            minifb::Key::NumPad4 => cpu.key[4] = false,
-            minifb::Key::NumPad5 => cpu.key[5] = false,
-            minifb::Key::NumPad6 => cpu.key[6] = false,
-            minifb::Key::NumPadMinus => cpu.key[7] = false,
-            minifb::Key::NumPad1 => cpu.key[8] = false,
-            minifb::Key::NumPad2 => cpu.key[9] = false,
-            minifb::Key::NumPad3 => cpu.key[10] = false,
-            minifb::Key::NumPadPlus => cpu.key[11] = false,
-            minifb::Key::Right => cpu.key[12] = false,
-            minifb::Key::NumPad0 => cpu.key[13] = false,
-            minifb::Key::NumPadDot => cpu.key[14] = false,
-            minifb::Key::NumPadEnter => cpu.key[15] = false,
-            _ => {}
-        }
+    if display.is_key_pressed(Key::F2) {
+        osd.toggle_messages();
+    }
+
+    if display.is_key_pressed(Key::F3) && *speed > SPEED_STEP {
+        *speed -= SPEED_STEP;
+        osd.message(&format!("Speed {}%", speed));
     }
 
-    continue_emulation
+    if display.is_key_pressed(Key::F4) && *speed < MAX_SPEED {
+        *speed += SPEED_STEP;
+        osd.message(&format!("Speed {}%", speed));
+    }
+
+    if display.is_key_pressed(Key::F11) {
+        if let Err(error) = display.toggle_fullscreen() {
+            osd.message(&error);
+        }
+    }
 }
@@ -0,0 +1,334 @@
+//! # Monitor
+//!
+//! A monitor follows the execution of the program instruction by instruction:
+//! the command line debugger ([`crate::debugger::Debugger`]) and the GDB stub
+//! ([`crate::gdb::GdbStub`]), which can stop it, the trace
+//! ([`crate::trace::Trace`]), the coverage ([`crate::coverage::Coverage`]),
+//! the profiler ([`crate::profiler::Profiler`]) or the debugger windows
+//! ([`crate::views::Views`]).
+
+use crate::cpu::{Access, AccessKind, CPU};
+
+pub trait Monitor {
+    /// Called at every frame, before the instructions of the frame.
+    fn poll(&mut self, _cpu: &mut CPU) {}
+
+    /// Called before the instructions of every frame the emulation advances,
+    /// `frame` counting them from 0, and `budget` being the number of
+    /// instructions the frame can execute.
+    fn start_frame(&mut self, _frame: u64, _budget: u32) {}
+
+    /// Whether the emulation is stopped, in which case the frames do not
+    /// advance.
+    fn is_stopped(&self) -> bool {
+        false
+    }
+
+    /// Whether the monitor ended the emulation, which stops before the next
+    /// frame.
+    fn is_over(&self) -> bool {
+        false
+    }
+
+    /// Called before executing each instruction, return whether it can be.
+    fn before_instruction(&mut self, _cpu: &CPU) -> bool {
+        true
+    }
+
+    /// Called after each instruction, [`CPU::accesses`] telling the memory
+    /// it accessed.
+    fn after_instruction(&mut self, _cpu: &CPU) {}
+
+    /// Called once the instructions of a frame are over: its budget is used,
+    /// or the CPU waits for the vertical blank. A debugger stopping the
+    /// program delays it until the program resumes.
+    fn end_frame(&mut self, _cpu: &CPU) {}
+
+    /// Called every time the screen is drawn, including while the emulation
+    /// is halted.
+    fn draw(&mut self, _cpu: &CPU) {}
+
+    /// Called once the emulation is over.
+    fn finish(&mut self, _cpu: &CPU) {}
+}
+
+/// Stop the program when it accesses a range of memory.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub length: u16,
+    pub read: bool,
+    pub write: bool,
+    /// Opcode fetches.
+    pub execute: bool,
+}
+
+impl Watchpoint {
+    pub fn matches(&self, access: &Access) -> bool {
+        let kind = match access.kind {
+            AccessKind::Execute => self.execute,
+            AccessKind::Read => self.read,
+            AccessKind::Write => self.write,
+        };
+        kind && access.address >= self.start && access.address - self.start < self.length
+    }
+
+    /// First access of the last instruction triggering a watchpoint.
+    pub fn find<'a>(watchpoints: &'a [Watchpoint], cpu: &CPU) -> Option<(&'a Watchpoint, Access)> {
+        cpu.accesses.iter().find_map(|access| {
+            watchpoints
+                .iter()
+                .find(|watchpoint| watchpoint.matches(access))
+                .map(|watchpoint| (watchpoint, *access))
+        })
+    }
+}
+
+/// Why the program stopped.
+#[derive(Clone, Copy)]
+pub enum Stop {
+    Breakpoint,
+    /// The access of the last instruction triggering a watchpoint.
+    Watchpoint(Watchpoint, Access),
+    /// The last of the steps was executed.
+    Step,
+}
+
+/// Breakpoints, watchpoints and steps of a monitor debugging the program,
+/// which stop it.
+pub struct Control {
+    pub breakpoints: Vec<u16>,
+    pub watchpoints: Vec<Watchpoint>,
+    stopped: bool,
+    // Instructions left to execute before stopping again
+    steps: u32,
+    // Do not stop again at the breakpoint the program is resumed from
+    resumed: bool,
+    // Stop once the instruction of the last step is executed
+    report: bool,
+    // Why the program stopped, until the monitor reports it
+    reason: Option<Stop>,
+}
+
+impl Control {
+    /// Control a program stopped on its first instruction.
+    pub fn new() -> Control {
+        Control {
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            stopped: true,
+            steps: 0,
+            resumed: false,
+            report: false,
+            reason: None,
+        }
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped && self.steps == 0
+    }
+
+    pub fn stop(&mut self) {
+        self.stopped = true;
+        self.steps = 0;
+        self.report = false;
+    }
+
+    /// Run until a breakpoint or watchpoint.
+    pub fn resume(&mut self) {
+        self.stopped = false;
+        self.resumed = true;
+    }
+
+    /// Execute a number of instructions, then stop.
+    pub fn step(&mut self, steps: u32) {
+        self.stopped = true;
+        self.steps = steps;
+    }
+
+    /// Let the program run freely.
+    pub fn detach(&mut self) {
+        self.stopped = false;
+        self.steps = 0;
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+    }
+
+    /// Why the program stopped since the last call, if it did.
+    pub fn take_stop(&mut self) -> Option<Stop> {
+        self.reason.take()
+    }
+
+    /// Return whether the instruction can be executed, see
+    /// [`Monitor::before_instruction`].
+    pub fn before_instruction(&mut self, cpu: &CPU) -> bool {
+        if self.stopped {
+            if self.steps == 0 {
+                return false;
+            }
+            self.steps -= 1;
+            self.report = self.steps == 0;
+            return true;
+        }
+
+        if self.breakpoints.contains(&cpu.pc) && !self.resumed {
+            self.stop();
+            self.reason = Some(Stop::Breakpoint);
+            return false;
+        }
+
+        self.resumed = false;
+        true
+    }
+
+    pub fn after_instruction(&mut self, cpu: &CPU) {
+        if let Some((watchpoint, access)) = Watchpoint::find(&self.watchpoints, cpu) {
+            self.reason = Some(Stop::Watchpoint(*watchpoint, access));
+            self.stop();
+        } else if self.report {
+            self.report = false;
+            self.reason = Some(Stop::Step);
+        }
+    }
+}
+
+impl Default for Control {
+    fn default() -> Control {
+        Control::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixel::Screen;
+
+    fn watchpoint(start: u16, length: u16, kinds: &str) -> Watchpoint {
+        Watchpoint {
+            start,
+            length,
+            read: kinds.contains('r'),
+            write: kinds.contains('w'),
+            execute: kinds.contains('x'),
+        }
+    }
+
+    fn access(address: u16, kind: AccessKind) -> Access {
+        Access { address, kind }
+    }
+
+    #[test]
+    fn matches_the_range_edges() {
+        let watchpoint = watchpoint(0x300, 4, "rwx");
+
+        assert!(!watchpoint.matches(&access(0x2FF, AccessKind::Read)));
+        assert!(watchpoint.matches(&access(0x300, AccessKind::Read)));
+        assert!(watchpoint.matches(&access(0x303, AccessKind::Read)));
+        assert!(!watchpoint.matches(&access(0x304, AccessKind::Read)));
+    }
+
+    #[test]
+    fn matches_the_watched_kinds() {
+        let address = 0x300;
+
+        assert!(watchpoint(address, 1, "r").matches(&access(address, AccessKind::Read)));
+        assert!(!watchpoint(address, 1, "r").matches(&access(address, AccessKind::Write)));
+        assert!(!watchpoint(address, 1, "r").matches(&access(address, AccessKind::Execute)));
+        assert!(watchpoint(address, 1, "w").matches(&access(address, AccessKind::Write)));
+        assert!(!watchpoint(address, 1, "wx").matches(&access(address, AccessKind::Read)));
+        assert!(watchpoint(address, 1, "x").matches(&access(address, AccessKind::Execute)));
+    }
+
+    #[test]
+    fn finds_the_first_access_of_the_instruction() {
+        let mut cpu = CPU::new();
+        let mut screen = Screen::new();
+        let watchpoints = [watchpoint(0x301, 2, "r"), watchpoint(0x301, 2, "w")];
+
+        // FX55 with I = 0x300 writes V0 and V1 to 0x300 and 0x301
+        cpu.i = 0x300;
+        cpu.execute_opcode(0xF155, &mut screen);
+        let (found, access) = Watchpoint::find(&watchpoints, &cpu).unwrap();
+        assert!(*found == watchpoints[1]);
+        assert!(access.address == 0x301 && access.kind == AccessKind::Write);
+
+        assert!(Watchpoint::find(&watchpoints[..1], &cpu).is_none());
+        assert!(Watchpoint::find(&[watchpoint(0x200, 1, "x")], &cpu).is_some());
+    }
+    /// Run instructions until the control stops them, returning how many ran.
+    fn run(control: &mut Control, cpu: &mut CPU, screen: &mut Screen, limit: u32) -> u32 {
+        let mut instructions = 0;
+        while instructions < limit && control.before_instruction(cpu) {
+            cpu.execute_opcode(cpu.get_opcode(), screen);
+            control.after_instruction(cpu);
+            instructions += 1;
+        }
+        instructions
+    }
+
+    // JP #200
+    fn looping_cpu() -> CPU {
+        let mut cpu = CPU::new();
+        cpu.memory[0x200..0x202].copy_from_slice(&[0x12, 0x00]);
+        cpu
+    }
+
+    #[test]
+    fn executes_the_steps_then_stops() {
+        let mut control = Control::new();
+        let mut cpu = looping_cpu();
+        let mut screen = Screen::new();
+
+        assert!(control.is_stopped());
+        assert_eq!(run(&mut control, &mut cpu, &mut screen, 10), 0);
+
+        control.step(3);
+        assert!(!control.is_stopped());
+        // The steps are left for the next frames when the budget is used
+        assert_eq!(run(&mut control, &mut cpu, &mut screen, 2), 2);
+        assert!(control.take_stop().is_none());
+        assert_eq!(run(&mut control, &mut cpu, &mut screen, 10), 1);
+        assert!(matches!(control.take_stop(), Some(Stop::Step)));
+        assert!(control.is_stopped());
+    }
+
+    #[test]
+    fn resumes_from_a_breakpoint() {
+        let mut control = Control::new();
+        let mut cpu = looping_cpu();
+        let mut screen = Screen::new();
+        control.breakpoints.push(0x200);
+
+        control.resume();
+        assert_eq!(run(&mut control, &mut cpu, &mut screen, 10), 1);
+        assert!(matches!(control.take_stop(), Some(Stop::Breakpoint)));
+        assert!(control.is_stopped());
+        assert_eq!(run(&mut control, &mut cpu, &mut screen, 10), 0);
+
+        // The breakpoint the program is resumed from does not stop it again
+        // before the next time it is reached
+        control.resume();
+        assert_eq!(run(&mut control, &mut cpu, &mut screen, 10), 1);
+        assert!(matches!(control.take_stop(), Some(Stop::Breakpoint)));
+    }
+
+    #[test]
+    fn stops_after_an_access_to_a_watchpoint() {
+        let mut control = Control::new();
+        let mut cpu = CPU::new();
+        let mut screen = Screen::new();
+        // LD I, #300 then LD [I], V1, which writes 0x300 and 0x301
+        cpu.memory[0x200..0x204].copy_from_slice(&[0xA3, 0x00, 0xF1, 0x55]);
+        control.watchpoints.push(watchpoint(0x301, 1, "w"));
+
+        control.resume();
+        assert_eq!(run(&mut control, &mut cpu, &mut screen, 10), 2);
+        let Some(Stop::Watchpoint(watchpoint, access)) = control.take_stop() else {
+            panic!("the watchpoint did not stop the program");
+        };
+        assert!(watchpoint == control.watchpoints[0]);
+        assert!(access.address == 0x301 && access.kind == AccessKind::Write);
+        assert!(control.is_stopped());
+        assert_eq!(cpu.pc, 0x204);
+    }
+}
@@ -0,0 +1,396 @@
+//! # Movie
+//!
+//! Recording of the keypad of a run, replayed to reproduce the run exactly.
+//! A movie file starts with everything else the run depends on, followed by
+//! the keypad state and the speed every time they change, numbered by frame:
+//!
+//! ```text
+//! chip8-movie 1
+//! rom 7a3b4e3c5e2f0e5d57fd1d6f7f8bbd8a6ab07b07
+//! seed 1234
+//! speed 100
+//! quirks display_wait=0 wrap_x=0 wrap_y=0
+//! 0 keys 0000
+//! 120 keys 0020
+//! 130 keys 0000
+//! 250 speed 125
+//! 600 end
+//! ```
+//!
+//! The keypad state is a mask where the bit `n` is set when the key `n` is
+//! down. Paused frames are not counted.
+
+use crate::frontend::Frontend;
+use crate::input::{Input, InputSource};
+use crate::quirks::Quirks;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+const MAGIC: &str = "chip8-movie 1";
+
+/// Settings of the run, which must be the same to replay it.
+pub struct Header {
+    pub rom_hash: String,
+    pub seed: u64,
+    pub speed: u32,
+    pub quirks: Quirks,
+}
+
+enum Event {
+    Keys([bool; 16]),
+    Speed(u32),
+    End,
+}
+
+/// Writes the input of a run to a movie file as it is played.
+pub struct Recorder {
+    writer: BufWriter<File>,
+    keys: Option<[bool; 16]>,
+    speed: u32,
+    // Nothing is written after an error
+    failed: bool,
+}
+
+impl Recorder {
+    pub fn create(path: &str, header: &Header) -> Result<Recorder, String> {
+        let file =
+            File::create(path).map_err(|error| format!("cannot create {}: {}", path, error))?;
+        let mut writer = BufWriter::new(file);
+
+        // The header is flushed so that a file which cannot be written is
+        // reported before the run starts
+        let quirks = header.quirks;
+        let write_header = |writer: &mut BufWriter<File>| -> io::Result<()> {
+            writeln!(writer, "{}", MAGIC)?;
+            writeln!(writer, "rom {}", header.rom_hash)?;
+            writeln!(writer, "seed {}", header.seed)?;
+            writeln!(writer, "speed {}", header.speed)?;
+            writeln!(
+                writer,
+                "quirks display_wait={} wrap_x={} wrap_y={}",
+                quirks.display_wait as u8, quirks.wrap_x as u8, quirks.wrap_y as u8
+            )?;
+            writer.flush()
+        };
+        write_header(&mut writer).map_err(|error| format!("cannot write {}: {}", path, error))?;
+
+        Ok(Recorder {
+            writer,
+            keys: None,
+            speed: header.speed,
+            failed: false,
+        })
+    }
+
+    /// Record the input of a frame, before it is executed.
+    pub fn record(&mut self, frame: u64, keys: [bool; 16], speed: u32) {
+        if self.keys != Some(keys) {
+            self.write(|writer| writeln!(writer, "{} keys {:04x}", frame, to_mask(keys)));
+            self.keys = Some(keys);
+        }
+        if self.speed != speed {
+            self.write(|writer| writeln!(writer, "{} speed {}", frame, speed));
+            self.speed = speed;
+        }
+    }
+
+    /// Mark the end of the run, `frames` being the number of frames played.
+    pub fn finish(&mut self, frames: u64) {
+        self.write(|writer| {
+            writeln!(writer, "{} end", frames)?;
+            writer.flush()
+        });
+    }
+
+    /// Write to the movie unless it already failed. A movie missing events
+    /// would not replay the run, so the recording stops at the first error
+    /// while the emulation goes on.
+    fn write(&mut self, write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>) {
+        if self.failed {
+            return;
+        }
+        if let Err(error) = write(&mut self.writer) {
+            println!("cannot write the movie: {}", error);
+            self.failed = true;
+        }
+    }
+}
+
+/// Replays the input of a movie file.
+pub struct Player {
+    pub header: Header,
+    events: Vec<(u64, Event)>,
+    next: usize,
+    keys: [bool; 16],
+    speed: u32,
+}
+
+impl Player {
+    pub fn load(path: &str) -> Result<Player, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| format!("cannot read movie {}: {}", path, error))?;
+        Player::parse(&text).map_err(|error| format!("{}: {}", path, error))
+    }
+
+    pub fn parse(text: &str) -> Result<Player, String> {
+        let mut lines = text.lines().enumerate();
+        let mut next_line = |name: &str| match lines.next() {
+            Some((number, line)) => Ok((number, line)),
+            None => Err(format!("missing {}", name)),
+        };
+
+        if next_line("header")?.1 != MAGIC {
+            return Err("not a chip8 movie".to_string());
+        }
+
+        let (number, line) = next_line("rom hash")?;
+        let rom_hash = field(line, "rom")
+            .ok_or(format!("line {}: expected rom <sha1>", number + 1))?
+            .to_string();
+
+        let (number, line) = next_line("seed")?;
+        let seed = field(line, "seed")
+            .and_then(|seed| seed.parse().ok())
+            .ok_or(format!("line {}: expected seed <n>", number + 1))?;
+
+        let (number, line) = next_line("speed")?;
+        let speed = field(line, "speed")
+            .and_then(|speed| speed.parse().ok())
+            .ok_or(format!("line {}: expected speed <percent>", number + 1))?;
+
+        let (number, line) = next_line("quirks")?;
+        let quirks = field(line, "quirks")
+            .and_then(parse_quirks)
+            .ok_or(format!("line {}: invalid quirks", number + 1))?;
+
+        let mut events = Vec::new();
+        for (number, line) in lines {
+            let error = || format!("line {}: invalid event: {}", number + 1, line);
+            let mut words = line.split_whitespace();
+            let frame = words
+                .next()
+                .and_then(|frame| frame.parse().ok())
+                .ok_or_else(error)?;
+            let event = match (words.next(), words.next()) {
+                (Some("keys"), Some(mask)) => Event::Keys(from_mask(
+                    u16::from_str_radix(mask, 16).map_err(|_| error())?,
+                )),
+                (Some("speed"), Some(speed)) => Event::Speed(speed.parse().map_err(|_| error())?),
+                (Some("end"), None) => Event::End,
+                _ => return Err(error()),
+            };
+            events.push((frame, event));
+        }
+
+        Ok(Player {
+            header: Header {
+                rom_hash,
+                seed,
+                speed,
+                quirks,
+            },
+            events,
+            next: 0,
+            keys: [false; 16],
+            speed,
+        })
+    }
+}
+
+/// A movie without an end, like the one of a run which crashed, keeps its
+/// last input.
+impl InputSource for Player {
+    fn read(&mut self, frame: u64, _display: &mut dyn Frontend) -> Option<Input> {
+        while let Some((event_frame, event)) = self.events.get(self.next) {
+            if *event_frame > frame {
+                break;
+            }
+            match event {
+                Event::Keys(keys) => self.keys = *keys,
+                Event::Speed(speed) => self.speed = *speed,
+                Event::End => return None,
+            }
+            self.next += 1;
+        }
+
+        Some(Input {
+            keypad: self.keys,
+            speed: Some(self.speed),
+        })
+    }
+}
+
+fn field<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    line.strip_prefix(name)?.strip_prefix(' ')
+}
+
+fn parse_quirks(text: &str) -> Option<Quirks> {
+    let mut quirks = Quirks::new();
+
+    for setting in text.split_whitespace() {
+        let (name, value) = setting.split_once('=')?;
+        let value = match value {
+            "0" => false,
+            "1" => true,
+            _ => return None,
+        };
+        match name {
+            "display_wait" => quirks.display_wait = value,
+            "wrap_x" => quirks.wrap_x = value,
+            "wrap_y" => quirks.wrap_y = value,
+            _ => return None,
+        }
+    }
+
+    Some(quirks)
+}
+
+fn to_mask(keys: [bool; 16]) -> u16 {
+    keys.iter()
+        .enumerate()
+        .filter(|(_, down)| **down)
+        .fold(0, |mask, (key, _)| mask | 1 << key)
+}
+
+fn from_mask(mask: u16) -> [bool; 16] {
+    std::array::from_fn(|key| mask & 1 << key != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::Headless;
+
+    const MOVIE: &str = "chip8-movie 1
+rom 7a3b4e3c5e2f0e5d57fd1d6f7f8bbd8a6ab07b07
+seed 1234
+speed 100
+quirks display_wait=1 wrap_x=0 wrap_y=1
+0 keys 0000
+2 keys 0020
+3 speed 125
+4 end
+";
+
+    fn replay(player: &mut Player, frames: u64) -> Vec<Option<(u16, u32)>> {
+        (0..frames)
+            .map(|frame| {
+                player
+                    .read(frame, &mut Headless)
+                    .map(|input| (to_mask(input.keypad), input.speed.unwrap()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_the_header() {
+        let header = Player::parse(MOVIE).unwrap().header;
+
+        assert_eq!(header.rom_hash, "7a3b4e3c5e2f0e5d57fd1d6f7f8bbd8a6ab07b07");
+        assert_eq!((header.seed, header.speed), (1234, 100));
+        let quirks = header.quirks;
+        assert!(quirks.display_wait && !quirks.wrap_x && quirks.wrap_y);
+    }
+
+    #[test]
+    fn replays_the_events() {
+        let mut player = Player::parse(MOVIE).unwrap();
+
+        assert_eq!(
+            replay(&mut player, 5),
+            [
+                Some((0x0000, 100)),
+                Some((0x0000, 100)),
+                Some((0x0020, 100)),
+                Some((0x0020, 125)),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_movies() {
+        let error = |text: &str| Player::parse(text).err().unwrap();
+        let header = MOVIE.lines().take(5).collect::<Vec<&str>>().join("\n");
+
+        assert_eq!(error("chip8-movie 2"), "not a chip8 movie");
+        assert_eq!(error("chip8-movie 1\nrom abc"), "missing seed");
+        assert_eq!(
+            error("chip8-movie 1\nrom abc\nseed x"),
+            "line 3: expected seed <n>"
+        );
+        assert_eq!(
+            error(&MOVIE.replace("wrap_x=0", "wrap_x=2")),
+            "line 5: invalid quirks"
+        );
+        assert_eq!(
+            error(&format!("{}\n7 keys", header)),
+            "line 6: invalid event: 7 keys"
+        );
+        assert_eq!(
+            error(&format!("{}\n7 end now", header)),
+            "line 6: invalid event: 7 end now"
+        );
+    }
+
+    #[test]
+    fn replays_what_it_records() {
+        let path = std::env::temp_dir().join(format!("chip8-movie-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let header = Header {
+            rom_hash: "abc".to_string(),
+            seed: 7,
+            speed: 100,
+            quirks: Quirks::new(),
+        };
+
+        let mut recorder = Recorder::create(path, &header).unwrap();
+        recorder.record(0, from_mask(0x0001), 100);
+        recorder.record(1, from_mask(0x0001), 150);
+        recorder.record(2, from_mask(0x8000), 150);
+        recorder.finish(3);
+        drop(recorder);
+        let player = Player::load(path);
+        std::fs::remove_file(path).unwrap();
+        let mut player = player.unwrap();
+
+        assert_eq!(player.header.seed, 7);
+        assert_eq!(
+            replay(&mut player, 4),
+            [
+                Some((0x0001, 100)),
+                Some((0x0001, 150)),
+                Some((0x8000, 150)),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn stops_recording_on_errors() {
+        let header = Header {
+            rom_hash: "abc".to_string(),
+            seed: 7,
+            speed: 100,
+            quirks: Quirks::new(),
+        };
+        assert!(Recorder::create("/dev/full", &header)
+            .err()
+            .unwrap()
+            .starts_with("cannot write /dev/full"));
+
+        // The disk gets full during the run
+        let mut recorder = Recorder {
+            writer: BufWriter::new(File::create("/dev/full").unwrap()),
+            keys: None,
+            speed: 100,
+            failed: false,
+        };
+        for frame in 0..10_000 {
+            recorder.record(frame, from_mask(frame as u16), 100);
+        }
+        assert!(recorder.failed);
+        recorder.finish(10_000);
+    }
+}
@@ -0,0 +1,343 @@
+//! # Options
+//!
+//! Command line options of the emulator.
+
+use crate::crt::Crt;
+use crate::input::ScriptInput;
+use crate::keymap::Keymap;
+use crate::movie::Player;
+use crate::pixel::{Scaling, DEFAULT_SCALE};
+use crate::quirks::{Platform, Quirks};
+use crate::terminal::{Palette, TerminalMode};
+use std::path::Path;
+
+pub const USAGE: &str = "Usage: chip8 [options] <rom_path>
+
+Options:
+    --scale <n>    initial window scale, or scale of terminal bitmaps
+                   (default: 8)
+    --stretch      fill the window keeping the aspect ratio instead of
+                   using the largest integer scale
+    --fullscreen <width>x<height>
+                   start in a borderless window covering a monitor of this
+                   size, `F11` switching back and forth
+    --terminal <mode>
+                   run in the terminal instead of a window, drawing with
+                   `halfblock` or `braille` characters, `sixel` or `kitty`
+                   bitmaps, or `auto` to detect bitmap support
+    --colors <foreground>,<background>
+                   ANSI colors of the screen in the terminal (default:
+                   `white,black`)
+    --crt <preset>[,<effect>=<strength>...]
+                   imitate a CRT monitor in the window with the `subtle`,
+                   `classic` or `heavy` preset, optionally overriding the
+                   strength (0 to 1) of the `scanlines`, `bloom`,
+                   `curvature`, `vignette` and `mask` effects
+    --platform <platform>
+                   behave like the `chip8` (default), `vip`, `schip` or
+                   `xochip` interpreters
+    --display-wait wait for the next frame after drawing a sprite, like
+                   the COSMAC VIP
+    --edges <mode> `clip` sprites at the edges of the screen, `wrap` them
+                   around, or only wrap them horizontally (`wrap-x`) or
+                   vertically (`wrap-y`)
+    --keymap <path>
+                   bind the Chip8 keys to the host keys listed in a keymap
+                   file instead of the profile of the ROM or the
+                   1234/QWER/ASDF/ZXCV layout
+    --profiles <directory>
+                   directory of the keymaps of the ROMs (default:
+                   ~/.config/chip8-emulator/profiles)
+    --keypad       show a virtual keypad next to the screen in the window,
+                   which can be clicked or touched
+    --seed <n>     seed of the random numbers, to make a run reproducible
+    --record-input <path>
+                   record the keypad to a movie file
+    --play-input <path>
+                   replay the keypad, seed, speed and quirks recorded in a
+                   movie file, then give the control back
+    --input-script <path>
+                   press the keys listed in a script file, then give the
+                   control back
+    --headless     run without display nor keyboard, as fast as possible
+    --debug        debug the program with commands typed in the standard
+                   input, `help` lists them
+    --gdb <port>   wait for GDB to connect to a local port, and let it
+                   debug the program
+    --symbols <path>
+                   name the addresses after a symbol file (default: the ROM
+                   path with the `.sym` extension, if it exists)
+    --views        open windows showing the memory, the registers and the
+                   disassembly
+    --trace <path> log every executed instruction and the registers to a
+                   file
+    --trace-range <start>-<end>
+                   only trace the instructions between two hexadecimal
+                   addresses
+    --trace-frames <first>-<last>
+                   only trace the instructions of these frames
+    --coverage <path>
+                   save how many times each address was executed, read and
+                   written to a JSON report
+    --heatmap <path>
+                   save the accesses to the memory as a BMP image
+    --profiler     count the executed instructions per opcode, address and
+                   subroutine, and print a summary on exit
+    --frames <n>   stop after this number of frames, by default headless
+                   runs stop at the end of the movie or script";
+
+pub struct Options {
+    pub rom_path: String,
+    pub scale: usize,
+    pub scaling: Scaling,
+    pub fullscreen: Option<(usize, usize)>,
+    pub terminal: Option<TerminalMode>,
+    pub palette: Palette,
+    pub crt: Option<Crt>,
+    pub quirks: Quirks,
+    pub keymap: Option<Keymap>,
+    pub profiles: Option<String>,
+    pub keypad: bool,
+    pub seed: Option<u64>,
+    pub record_input: Option<String>,
+    pub play_input: Option<Player>,
+    pub input_script: Option<ScriptInput>,
+    pub headless: bool,
+    pub frames: Option<u64>,
+    pub debug: bool,
+    pub gdb: Option<u16>,
+    pub symbols: Option<String>,
+    pub views: bool,
+    pub trace: Option<String>,
+    pub trace_range: Option<(u16, u16)>,
+    pub trace_frames: Option<(u64, u64)>,
+    pub coverage: Option<String>,
+    pub heatmap: Option<String>,
+    pub profiler: bool,
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut rom_path = None;
+        let mut scale = DEFAULT_SCALE;
+        let mut scaling = Scaling::Integer;
+        let mut fullscreen = None;
+        let mut terminal = None;
+        let mut palette = Palette::new();
+        let mut crt = None;
+        let mut platform = Platform::Chip8;
+        let mut display_wait = false;
+        let mut edges = None;
+        let mut keymap = None;
+        let mut profiles = None;
+        let mut keypad = false;
+        let mut seed = None;
+        let mut record_input = None;
+        let mut play_input = None;
+        let mut input_script = None;
+        let mut headless = false;
+        let mut frames = None;
+        let mut debug = false;
+        let mut gdb = None;
+        let mut symbols = None;
+        let mut views = false;
+        let mut trace = None;
+        let mut trace_range = None;
+        let mut trace_frames = None;
+        let mut coverage = None;
+        let mut heatmap = None;
+        let mut profiler = false;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--scale" => {
+                    let value = args.next().ok_or("--scale expects a value")?;
+                    scale = match value.parse() {
+                        Ok(scale) if scale > 0 => scale,
+                        _ => return Err(format!("invalid scale: {}", value)),
+                    };
+                }
+                "--stretch" => scaling = Scaling::Aspect,
+                "--fullscreen" => {
+                    let value = args.next().ok_or("--fullscreen expects a size")?;
+                    fullscreen = Some(parse_size(value).ok_or(format!("invalid size: {}", value))?);
+                }
+                "--terminal" => {
+                    let value = args.next().ok_or("--terminal expects a mode")?;
+                    terminal = Some(
+                        TerminalMode::parse(value)
+                            .ok_or(format!("invalid terminal mode: {}", value))?,
+                    );
+                }
+                "--colors" => {
+                    let value = args.next().ok_or("--colors expects two colors")?;
+                    palette = Palette::parse(value)?;
+                }
+                "--crt" => {
+                    let value = args.next().ok_or("--crt expects a preset")?;
+                    crt = Some(Crt::parse(value)?);
+                }
+                "--platform" => {
+                    let value = args.next().ok_or("--platform expects a platform")?;
+                    platform =
+                        Platform::parse(value).ok_or(format!("invalid platform: {}", value))?;
+                }
+                "--display-wait" => display_wait = true,
+                "--edges" => edges = Some(args.next().ok_or("--edges expects a mode")?),
+                "--keymap" => {
+                    let path = args.next().ok_or("--keymap expects a path")?;
+                    keymap = Some(Keymap::load(Path::new(path))?);
+                }
+                "--profiles" => {
+                    let path = args.next().ok_or("--profiles expects a directory")?;
+                    profiles = Some(path.clone());
+                }
+                "--keypad" => keypad = true,
+                "--seed" => {
+                    let value = args.next().ok_or("--seed expects a number")?;
+                    seed = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("invalid seed: {}", value))?,
+                    );
+                }
+                "--record-input" => {
+                    let path = args.next().ok_or("--record-input expects a path")?;
+                    record_input = Some(path.clone());
+                }
+                "--play-input" => {
+                    let path = args.next().ok_or("--play-input expects a path")?;
+                    play_input = Some(Player::load(path)?);
+                }
+                "--input-script" => {
+                    let path = args.next().ok_or("--input-script expects a path")?;
+                    input_script = Some(ScriptInput::load(Path::new(path))?);
+                }
+                "--headless" => headless = true,
+                "--debug" => debug = true,
+                "--gdb" => {
+                    let value = args.next().ok_or("--gdb expects a port")?;
+                    gdb = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("invalid port: {}", value))?,
+                    );
+                }
+                "--frames" => {
+                    let value = args.next().ok_or("--frames expects a number")?;
+                    frames = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("invalid number of frames: {}", value))?,
+                    );
+                }
+                "--symbols" => {
+                    let path = args.next().ok_or("--symbols expects a path")?;
+                    symbols = Some(path.clone());
+                }
+                "--views" => views = true,
+                "--trace" => {
+                    let path = args.next().ok_or("--trace expects a path")?;
+                    trace = Some(path.clone());
+                }
+                "--trace-range" => {
+                    let value = args.next().ok_or("--trace-range expects a range")?;
+                    let range = parse_range(value, |address| {
+                        u16::from_str_radix(address, 16)
+                            .ok()
+                            .filter(|address| *address < 0x1000)
+                    });
+                    trace_range = Some(range.ok_or(format!("invalid range: {}", value))?);
+                }
+                "--trace-frames" => {
+                    let value = args.next().ok_or("--trace-frames expects a range")?;
+                    let frames = parse_range(value, |frame| frame.parse().ok());
+                    trace_frames = Some(frames.ok_or(format!("invalid frames: {}", value))?);
+                }
+                "--coverage" => {
+                    let path = args.next().ok_or("--coverage expects a path")?;
+                    coverage = Some(path.clone());
+                }
+                "--heatmap" => {
+                    let path = args.next().ok_or("--heatmap expects a path")?;
+                    heatmap = Some(path.clone());
+                }
+                "--profiler" => profiler = true,
+                _ if arg.starts_with("--") => return Err(format!("unknown option: {}", arg)),
+                _ if rom_path.is_none() => rom_path = Some(arg.clone()),
+                _ => return Err(format!("unexpected argument: {}", arg)),
+            }
+        }
+
+        // Options overriding the quirks apply whatever their position
+        let mut quirks = platform.quirks();
+        quirks.display_wait |= display_wait;
+        if let Some(edges) = edges {
+            quirks.set_edges(edges)?;
+        }
+
+        if play_input.is_some() && input_script.is_some() {
+            return Err("--play-input and --input-script cannot be used together".to_string());
+        }
+        if debug && terminal.is_some() {
+            return Err("--debug cannot be used with --terminal".to_string());
+        }
+        if debug && gdb.is_some() {
+            return Err("--debug and --gdb cannot be used together".to_string());
+        }
+        if views && headless {
+            return Err("--views cannot be used with --headless".to_string());
+        }
+        if trace.is_none() && (trace_range.is_some() || trace_frames.is_some()) {
+            return Err("--trace-range and --trace-frames expect --trace".to_string());
+        }
+        if headless && frames.is_none() && play_input.is_none() && input_script.is_none() {
+            return Err("--headless expects --frames, --play-input or --input-script".to_string());
+        }
+
+        Ok(Options {
+            rom_path: rom_path.ok_or("missing rom path")?,
+            scale,
+            scaling,
+            fullscreen,
+            terminal,
+            palette,
+            crt,
+            quirks,
+            keymap,
+            profiles,
+            keypad,
+            seed,
+            record_input,
+            play_input,
+            input_script,
+            headless,
+            frames,
+            debug,
+            gdb,
+            symbols,
+            views,
+            trace,
+            trace_range,
+            trace_frames,
+            coverage,
+            heatmap,
+            profiler,
+        })
+    }
+}
+
+/// Parse `<width>x<height>`, both being positive.
+fn parse_size(text: &str) -> Option<(usize, usize)> {
+    let (width, height) = text.split_once('x')?;
+    let (width, height) = (width.parse().ok()?, height.parse().ok()?);
+    (width > 0 && height > 0).then_some((width, height))
+}
+
+/// Parse `<first>-<last>`, `first` not being after `last`.
+fn parse_range<T: PartialOrd>(text: &str, parse: impl Fn(&str) -> Option<T>) -> Option<(T, T)> {
+    let (first, last) = text.split_once('-')?;
+    let (first, last) = (parse(first)?, parse(last)?);
+    (first <= last).then_some((first, last))
+}
@@ -0,0 +1,135 @@
+//! # OSD
+//!
+//! On-screen display composited over the Chip8 screen in the window:
+//! transient messages, frame and instruction counters, ROM name and pause
+//! indicator. It only draws in the window buffer, never in emulated memory.
+
+use crate::font::{self, Canvas};
+use std::time::{Duration, Instant};
+
+const MESSAGE_DURATION: Duration = Duration::from_secs(2);
+const TEXT_COLOR: u32 = 0xFFFF00;
+const BACKGROUND_COLOR: u32 = 0x202020;
+
+// Window pixels between the text and the border of its background
+const PADDING: usize = 2;
+
+pub struct Osd {
+    rom_name: String,
+    show_stats: bool,
+    show_messages: bool,
+    paused: bool,
+    message: Option<(String, Instant)>,
+    second_start: Instant,
+    frames: u32,
+    instructions: u32,
+    fps: u32,
+    ips: u32,
+}
+
+impl Osd {
+    pub fn new(rom_name: &str) -> Osd {
+        Osd {
+            rom_name: rom_name.to_string(),
+            show_stats: false,
+            show_messages: true,
+            paused: false,
+            message: None,
+            second_start: Instant::now(),
+            frames: 0,
+            instructions: 0,
+            fps: 0,
+            ips: 0,
+        }
+    }
+
+    /// Show a message for a few seconds, unless messages are disabled.
+    pub fn message(&mut self, text: &str) {
+        if self.show_messages {
+            self.message = Some((text.to_string(), Instant::now()));
+        }
+    }
+
+    pub fn toggle_stats(&mut self) {
+        self.show_stats = !self.show_stats;
+    }
+
+    pub fn toggle_messages(&mut self) {
+        self.show_messages = !self.show_messages;
+        self.message = None;
+        self.message("Messages on");
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// Count a frame and the instructions executed during it.
+    pub fn frame(&mut self, instructions: u32) {
+        self.frames += 1;
+        self.instructions += instructions;
+
+        let elapsed = self.second_start.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            self.fps = (self.frames as f32 / elapsed.as_secs_f32()).round() as u32;
+            self.ips = (self.instructions as f32 / elapsed.as_secs_f32()).round() as u32;
+            self.frames = 0;
+            self.instructions = 0;
+            self.second_start = Instant::now();
+        }
+    }
+
+    fn current_message(&self) -> Option<&str> {
+        match &self.message {
+            Some((text, time)) if time.elapsed() < MESSAGE_DURATION => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Whether anything has to be drawn over the screen.
+    pub fn is_visible(&self) -> bool {
+        self.show_stats || self.paused || self.current_message().is_some()
+    }
+
+    /// Draw the OSD over a `width` x `height` window buffer.
+    pub fn draw(&self, buffer: &mut [u32], width: usize, height: usize) {
+        let mut canvas = Canvas {
+            buffer,
+            width,
+            height,
+        };
+        // Keep the text readable whatever the window size
+        let scale = (height / 128).max(1);
+        let line_height = font::GLYPH_HEIGHT * scale + 2 * PADDING + 1;
+
+        if self.show_stats {
+            let stats = format!("FPS {}  IPS {}", self.fps, self.ips);
+            draw_label(&mut canvas, 0, 0, &stats, scale);
+            draw_label(&mut canvas, 0, line_height, &self.rom_name, scale);
+        }
+
+        if self.paused {
+            let (text_width, _) = font::text_size("PAUSED", scale);
+            let x = width.saturating_sub(text_width + 2 * PADDING);
+            draw_label(&mut canvas, x, 0, "PAUSED", scale);
+        }
+
+        if let Some(message) = self.current_message() {
+            let y = height.saturating_sub(line_height);
+            draw_label(&mut canvas, 0, y, message, scale);
+        }
+    }
+}
+
+/// Draw a text on a background from the top left corner `(x, y)`.
+fn draw_label(canvas: &mut Canvas, x: usize, y: usize, text: &str, scale: usize) {
+    let (text_width, text_height) = font::text_size(text, scale);
+    canvas.fill_rect(
+        x,
+        y,
+        text_width + 2 * PADDING,
+        text_height + 2 * PADDING,
+        BACKGROUND_COLOR,
+    );
+    canvas.draw_text(x + PADDING, y + PADDING, text, scale, TEXT_COLOR);
+}
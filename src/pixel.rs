@@ -1,144 +1,445 @@
 //! # Pixel
 
 use crate::cpu::CPU;
-use minifb::{Key, Window, WindowOptions};
+use crate::crt::Crt;
+use crate::font::Canvas;
+use crate::frontend::Frontend;
+use crate::keypad::{Area, Keypad};
+use crate::osd::Osd;
+use minifb::{
+    InputCallback, Key, KeyRepeat, MouseButton, MouseMode, ScaleMode, Window, WindowOptions,
+};
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::time::Duration;
 
 const BLACK: u8 = 0;
 const WHITE: u8 = 1;
-const LENGTH_WIDTH: u16 = 64;
-const LENGTH_HEIGHT: u16 = 32;
-const PIXEL_SIZE: u16 = 8;
-const WIDTH: u32 = (LENGTH_WIDTH * PIXEL_SIZE) as u32;
-const HEIGHT: u32 = (LENGTH_HEIGHT * PIXEL_SIZE) as u32;
+pub const LENGTH_WIDTH: u16 = 64;
+pub const LENGTH_HEIGHT: u16 = 32;
+/// Size of the screen in the high resolution mode of the SUPER-CHIP.
+pub const HIRES_WIDTH: u16 = 128;
+pub const HIRES_HEIGHT: u16 = 64;
+pub const DEFAULT_SCALE: usize = 8;
+
+/// How the Chip8 screen is fitted into the window.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Scaling {
+    /// Largest integer scale that fits, so every Chip8 pixel has the same size.
+    Integer,
+    /// Fill the window as much as possible while keeping the 2:1 aspect ratio.
+    Aspect,
+}
+
+/// Area of the window the Chip8 screen is drawn into, the rest is letterboxed.
+struct Viewport {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+impl Scaling {
+    /// Area of a `width` x `height` window showing a screen of
+    /// `screen_width` x `screen_height` Chip8 pixels.
+    fn viewport(
+        &self,
+        width: usize,
+        height: usize,
+        screen_width: usize,
+        screen_height: usize,
+    ) -> Viewport {
+        let (view_width, view_height) = match self {
+            Scaling::Integer => {
+                let scale = (width / screen_width).min(height / screen_height).max(1);
+                (screen_width * scale, screen_height * scale)
+            }
+            Scaling::Aspect => {
+                if width * screen_height >= height * screen_width {
+                    (height * screen_width / screen_height, height)
+                } else {
+                    (width, width * screen_height / screen_width)
+                }
+            }
+        };
+
+        Viewport {
+            x: width.saturating_sub(view_width) / 2,
+            y: height.saturating_sub(view_height) / 2,
+            width: view_width,
+            height: view_height,
+        }
+    }
+}
 
 pub struct PixelDisplay {
     window: Window,
     buffer: Vec<u32>,
+    width: usize,
+    height: usize,
+    scaling: Scaling,
+    crt: Option<Crt>,
+    // Frame with the OSD drawn over it
+    output: Vec<u32>,
+    overlaid: bool,
+    keypad: Option<Keypad>,
+    // Keys pressed since the last frame, even if already released
+    taps: Rc<RefCell<Vec<Key>>>,
+    // Size of the normal window, and of the monitor if known
+    window_size: (usize, usize),
+    fullscreen_size: Option<(usize, usize)>,
+    fullscreen: bool,
+    // Size of the screen last drawn, the whole window being redrawn when the
+    // program switches resolution
+    resolution: (u16, u16),
 }
 
 impl PixelDisplay {
-    pub fn new() -> PixelDisplay {
-        let mut window = Window::new(
-            "Chip8 Emulator",
-            WIDTH as usize,
-            HEIGHT as usize,
-            WindowOptions::default(),
-        )
-        .unwrap();
+    /// Open the window, or a fullscreen window if `fullscreen` gives the
+    /// size of the monitor.
+    pub fn new(
+        scale: usize,
+        scaling: Scaling,
+        crt: Option<Crt>,
+        keypad: bool,
+        fullscreen: Option<(usize, usize)>,
+    ) -> PixelDisplay {
+        let height = LENGTH_HEIGHT as usize * scale;
+        let mut width = LENGTH_WIDTH as usize * scale;
+        if keypad {
+            // Room for a square keypad on the right
+            width += height;
+        }
 
-        window.limit_update_rate(Some(Duration::from_millis(1000 / 60)));
+        let taps = Rc::new(RefCell::new(Vec::new()));
+        let window = match fullscreen {
+            Some((width, height)) => open_window(width, height, true, &taps),
+            None => open_window(width, height, false, &taps),
+        };
 
         PixelDisplay {
             window,
-            buffer: vec![0; (WIDTH * HEIGHT) as usize],
+            buffer: vec![0; width * height],
+            width,
+            height,
+            scaling,
+            crt,
+            output: Vec::new(),
+            overlaid: false,
+            keypad: keypad.then(Keypad::new),
+            taps,
+            window_size: (width, height),
+            fullscreen_size: fullscreen,
+            fullscreen: fullscreen.is_some(),
+            resolution: (LENGTH_WIDTH, LENGTH_HEIGHT),
         }
     }
 
-    pub fn draw(&mut self, pixels: &Vec<Pixel>) {
-        for pixel in pixels {
-            let x = pixel.position.x * PIXEL_SIZE;
-            let y = pixel.position.y * PIXEL_SIZE;
-            let color = if pixel.color == BLACK { 0 } else { 0xFFFFFF };
+    /// Follow the window size, which changes when the user resizes the window
+    /// or the window manager makes it fullscreen, and the resolution of the
+    /// screen. Return whether either changed.
+    fn resize(&mut self, screen: &Screen) -> bool {
+        let (width, height) = self.window.get_size();
+        let resolution = (screen.width, screen.height);
+
+        if width != self.width || height != self.height || resolution != self.resolution {
+            self.width = width;
+            self.height = height;
+            self.resolution = resolution;
+            // Clearing the whole buffer also paints the letterbox borders black
+            self.buffer = vec![0; width * height];
+            return true;
+        }
+
+        false
+    }
+
+    /// Area of the virtual keypad, on the right of the window.
+    fn keypad_area(&self) -> Option<Area> {
+        self.keypad.as_ref()?;
+        let size = self.height.min(self.width / 2);
+
+        Some(Area {
+            x: self.width - size,
+            y: (self.height - size) / 2,
+            size,
+        })
+    }
+
+    /// Draw the rows of the screen which changed, or all of them.
+    fn draw_rows(&mut self, screen: &Screen, all: bool) {
+        let keypad_size = self.keypad_area().map_or(0, |area| area.size);
+        let (screen_width, screen_height) = (screen.width as usize, screen.height as usize);
+        let viewport = self.scaling.viewport(
+            self.width - keypad_size,
+            self.height,
+            screen_width,
+            screen_height,
+        );
+
+        for (y, row) in screen.pixels.chunks(screen_width).enumerate() {
+            if !all && !screen.dirty_rows[y] {
+                continue;
+            }
+
+            // Chip8 pixels may cover a different number of window pixels when
+            // the scale is not an integer, so compute the covered span
+            let y_start = viewport.y + y * viewport.height / screen_height;
+            let y_end = viewport.y + (y + 1) * viewport.height / screen_height;
+
+            for pixel in row {
+                let x = pixel.position.x as usize;
+                let color = if pixel.color == BLACK { 0 } else { 0xFFFFFF };
+                let x_start = viewport.x + x * viewport.width / screen_width;
+                let x_end = viewport.x + (x + 1) * viewport.width / screen_width;
 
-            for i in 0..PIXEL_SIZE {
-                for j in 0..PIXEL_SIZE {
-                    let index = (y + i) as u32 * WIDTH + x as u32 + j as u32;
-                    self.buffer[index as usize] = color;
+                for i in y_start..y_end.min(self.height) {
+                    for j in x_start..x_end.min(self.width) {
+                        self.buffer[i * self.width + j] = color;
+                    }
                 }
             }
         }
+    }
+}
+
+impl Frontend for PixelDisplay {
+    fn draw(&mut self, screen: &Screen, osd: &Osd) {
+        let resized = self.resize(screen);
+        let overlay = osd.is_visible();
+        let keypad_changed = self.keypad.as_mut().is_some_and(Keypad::take_changed);
+
+        // Nothing changed since the last frame, only process window events
+        if !resized && !screen.is_dirty() && !overlay && !self.overlaid && !keypad_changed {
+            self.window.update();
+            return;
+        }
+
+        if resized || screen.is_dirty() {
+            self.draw_rows(screen, resized);
+            if let Some(crt) = &mut self.crt {
+                crt.apply(&self.buffer, self.width, self.height);
+            }
+        }
+
+        let frame = match &self.crt {
+            Some(crt) => crt.output(),
+            None => &self.buffer,
+        };
 
-        self.window
-            .update_with_buffer(&self.buffer, WIDTH as usize, HEIGHT as usize)
-            .unwrap();
-    }
-
-    pub fn get_key_down(&mut self) -> Option<Key> {
-        let keys = [
-            Key::Escape,
-            Key::NumPad7,
-            Key::NumPad8,
-            Key::NumPad9,
-            Key::NumPadAsterisk,
-            Key::NumPad4,
-            Key::NumPad5,
-            Key::NumPad6,
-            Key::NumPadMinus,
-            Key::NumPad1,
-            Key::NumPad2,
-            Key::NumPad3,
-            Key::NumPadPlus,
-            Key::Right,
-            Key::NumPad0,
-            Key::NumPadDot,
-            Key::NumPadEnter,
-        ];
-
-        for key in keys.iter() {
-            if self.window.is_key_down(*key) {
-                return Some(*key);
+        // The OSD and the keypad are drawn on a copy so the frame stays clean
+        // for the next one. The OSD is drawn once more after it disappears
+        // to erase it.
+        self.overlaid = overlay;
+        if overlay || self.keypad.is_some() {
+            self.output.clear();
+            self.output.extend_from_slice(frame);
+            if let (Some(keypad), Some(area)) = (&self.keypad, self.keypad_area()) {
+                let mut canvas = Canvas {
+                    buffer: &mut self.output,
+                    width: self.width,
+                    height: self.height,
+                };
+                keypad.draw(&mut canvas, &area);
             }
+            osd.draw(&mut self.output, self.width, self.height);
+            self.window
+                .update_with_buffer(&self.output, self.width, self.height)
+                .unwrap();
+        } else {
+            self.window
+                .update_with_buffer(frame, self.width, self.height)
+                .unwrap();
         }
+    }
+
+    fn is_key_pressed(&mut self, key: Key) -> bool {
+        self.window.is_key_pressed(key, KeyRepeat::No)
+    }
 
-        None
-    }
-
-    pub fn get_key_up(&mut self) -> Option<Key> {
-        let keys = [
-            Key::Escape,
-            Key::NumPad7,
-            Key::NumPad8,
-            Key::NumPad9,
-            Key::NumPadAsterisk,
-            Key::NumPad4,
-            Key::NumPad5,
-            Key::NumPad6,
-            Key::NumPadMinus,
-            Key::NumPad1,
-            Key::NumPad2,
-            Key::NumPad3,
-            Key::NumPadPlus,
-            Key::Right,
-            Key::NumPad0,
-            Key::NumPadDot,
-            Key::NumPadEnter,
-        ];
-
-        for key in keys.iter() {
-            if self.window.is_key_released(*key) {
-                return Some(*key);
+    fn is_open(&self) -> bool {
+        self.window.is_open()
+    }
+
+    fn toggle_fullscreen(&mut self) -> Result<(), String> {
+        let size = self
+            .fullscreen_size
+            .ok_or("Fullscreen needs --fullscreen <width>x<height>")?;
+
+        self.fullscreen = !self.fullscreen;
+        let (width, height) = if self.fullscreen {
+            size
+        } else {
+            self.window_size
+        };
+        self.window = open_window(width, height, self.fullscreen, &self.taps);
+        // The new window is blank, even if it has the same size
+        self.width = 0;
+
+        Ok(())
+    }
+
+    fn get_keys_down(&mut self) -> Vec<Key> {
+        let mut keys = self.window.get_keys();
+        for key in self.taps.borrow_mut().drain(..) {
+            if !keys.contains(&key) {
+                keys.push(key);
             }
         }
+        keys
+    }
+
+    fn get_virtual_keys(&mut self) -> [bool; 16] {
+        let mut keys = [false; 16];
 
-        None
-    }
-
-    pub fn wait_key_pressed(&mut self, cpu: &mut CPU) {
-        loop {
-            if let Some(key) = self.get_key_down() {
-                match key {
-                    Key::NumPad7 => cpu.key[0] = true,
-                    Key::NumPad8 => cpu.key[1] = true,
-                    Key::NumPad9 => cpu.key[2] = true,
-                    Key::NumPadAsterisk => cpu.key[3] = true,
-                    Key::NumPad4 => cpu.key[4] = true,
-                    Key::NumPad5 => cpu.key[5] = true,
-                    Key::NumPad6 => cpu.key[6] = true,
-                    Key::NumPadMinus => cpu.key[7] = true,
-                    Key::NumPad1 => cpu.key[8] = true,
-                    Key::NumPad2 => cpu.key[9] = true,
-                    Key::NumPad3 => cpu.key[10] = true,
-                    Key::NumPadPlus => cpu.key[11] = true,
-                    Key::Right => cpu.key[12] = true,
-                    Key::NumPad0 => cpu.key[13] = true,
-                    Key::NumPadDot => cpu.key[14] = true,
-                    Key::NumPadEnter => cpu.key[15] = true,
-                    _ => {}
+        if let (Some(keypad), Some(area)) = (&self.keypad, self.keypad_area()) {
+            let position = self.window.get_mouse_pos(MouseMode::Discard);
+            if let Some((x, y)) = position.filter(|_| self.window.get_mouse_down(MouseButton::Left))
+            {
+                if let Some(key) = keypad.key_at(&area, x as usize, y as usize) {
+                    keys[key] = true;
                 }
             }
-            break;
         }
+
+        keys
+    }
+
+    fn show_keypad(&mut self, keys: [bool; 16], polled: [bool; 16]) {
+        if let Some(keypad) = &mut self.keypad {
+            keypad.update(keys, polled);
+        }
+    }
+}
+
+/// Records the keys pressed. The window only tells which keys are down when
+/// it is updated, which misses the keys pressed and released in between.
+struct KeyLatch(Rc<RefCell<Vec<Key>>>);
+
+impl InputCallback for KeyLatch {
+    fn add_char(&mut self, _character: u32) {}
+
+    fn set_key_state(&mut self, key: Key, down: bool) {
+        if down {
+            self.0.borrow_mut().push(key);
+        }
+    }
+}
+
+/// Open a resizable window, or a borderless one covering the monitor from its
+/// top-left corner, minifb having no fullscreen mode. The keys pressed are
+/// latched into `taps`.
+fn open_window(
+    width: usize,
+    height: usize,
+    fullscreen: bool,
+    taps: &Rc<RefCell<Vec<Key>>>,
+) -> Window {
+    let mut window = Window::new(
+        "Chip8 Emulator",
+        width,
+        height,
+        WindowOptions {
+            borderless: fullscreen,
+            topmost: fullscreen,
+            resize: !fullscreen,
+            scale_mode: ScaleMode::UpperLeft,
+            ..WindowOptions::default()
+        },
+    )
+    .unwrap();
+
+    if fullscreen {
+        window.set_position(0, 0);
+    }
+    window.set_input_callback(Box::new(KeyLatch(Rc::clone(taps))));
+    window.limit_update_rate(Some(Duration::from_millis(1000 / 60)));
+
+    window
+}
+
+/// The Chip8 screen, which keeps track of the rows changed since it was last
+/// presented so that frontends only redraw those.
+pub struct Screen {
+    pub pixels: Vec<Pixel>,
+    width: u16,
+    height: u16,
+    dirty_rows: Vec<bool>,
+}
+
+impl Screen {
+    pub fn new() -> Screen {
+        Screen::with_size(LENGTH_WIDTH, LENGTH_HEIGHT)
+    }
+
+    fn with_size(width: u16, height: u16) -> Screen {
+        Screen {
+            pixels: Pixel::init(width, height),
+            width,
+            height,
+            // Everything must be drawn the first time
+            dirty_rows: vec![true; height as usize],
+        }
+    }
+
+    /// Width in Chip8 pixels, which depends on the resolution.
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    pub fn is_on(&self, x: u16, y: u16) -> bool {
+        self.pixels[(y * self.width + x) as usize].is_on()
+    }
+
+    /// Switch to the high or the low resolution, which clears the screen.
+    pub fn set_hires(&mut self, hires: bool) {
+        *self = if hires {
+            Screen::with_size(HIRES_WIDTH, HIRES_HEIGHT)
+        } else {
+            Screen::new()
+        };
+    }
+
+    /// Move the pixels `dx` columns to the right and `dy` rows down, the
+    /// pixels scrolled in being off.
+    pub fn scroll(&mut self, dx: i32, dy: i32) {
+        let (width, height) = (self.width as i32, self.height as i32);
+        let colors: Vec<u8> = self.pixels.iter().map(|pixel| pixel.color).collect();
+
+        for pixel in self.pixels.iter_mut() {
+            let x = pixel.position.x as i32 - dx;
+            let y = pixel.position.y as i32 - dy;
+            let color = if (0..width).contains(&x) && (0..height).contains(&y) {
+                colors[(y * width + x) as usize]
+            } else {
+                BLACK
+            };
+
+            if pixel.color != color {
+                pixel.color = color;
+                self.dirty_rows[pixel.position.y as usize] = true;
+            }
+        }
+    }
+
+    /// Whether any row changed since the last call to [`Screen::presented`].
+    pub fn is_dirty(&self) -> bool {
+        self.dirty_rows.contains(&true)
+    }
+
+    /// Mark the screen as up to date once a frontend has drawn it.
+    pub fn presented(&mut self) {
+        self.dirty_rows.fill(false);
+    }
+}
+
+impl Default for Screen {
+    fn default() -> Screen {
+        Screen::new()
     }
 }
 
@@ -160,11 +461,15 @@ impl Pixel {
         }
     }
 
-    pub fn init() -> Vec<Pixel> {
+    pub fn is_on(&self) -> bool {
+        self.color == WHITE
+    }
+
+    pub fn init(width: u16, height: u16) -> Vec<Pixel> {
         let mut pixels = Vec::new();
 
-        for i in 0..LENGTH_HEIGHT {
-            for j in 0..LENGTH_WIDTH {
+        for i in 0..height {
+            for j in 0..width {
                 let color = BLACK;
                 let pixel = Pixel::new(j, i, color);
                 pixels.push(pixel);
@@ -174,43 +479,431 @@ impl Pixel {
         pixels
     }
 
-    pub fn clear_screen(pixels: &mut Vec<Pixel>) {
-        for pixel in pixels.iter_mut() {
-            pixel.color = BLACK;
+    pub fn clear_screen(screen: &mut Screen) {
+        for pixel in screen.pixels.iter_mut() {
+            if pixel.color != BLACK {
+                pixel.color = BLACK;
+                screen.dirty_rows[pixel.position.y as usize] = true;
+            }
         }
     }
 
-    pub fn draw_screen(cpu: &mut CPU, pixels: &mut Vec<Pixel>, b1: u16, b2: u16, b3: u16) {
-        let mut x;
-        let mut y;
-        let mut code;
-        let mut shift;
-        cpu.v[0xF] = 0;
+    pub fn draw_screen(cpu: &mut CPU, screen: &mut Screen, b1: u16, b2: u16, b3: u16) {
+        let (width, height) = (screen.width, screen.height);
+        // DXY0 draws a 16x16 sprite of two bytes per row, like the SUPER-CHIP
+        let (rows, columns) = if b1 == 0 { (16, 16) } else { (b1, 8) };
+        let row_bytes = columns / 8;
 
-        for k in 0..b1 {
-            // Get the code of the line to draw
-            code = cpu.memory[(cpu.i + k) as usize];
+        // The sprite starts at a position wrapped around the screen, then is
+        // either clipped or wrapped at each edge depending on the platform
+        let start_x = cpu.v[b3 as usize] as u16 % width;
+        let start_y = cpu.v[b2 as usize] as u16 % height;
+        cpu.v[0xF] = 0;
 
+        for k in 0..rows {
             // Get the ordinate of the line to draw
-            y = ((cpu.v[b2 as usize] + k as u8) % LENGTH_HEIGHT as u8) as u16;
+            let mut y = start_y + k;
+            if y >= height {
+                if !cpu.quirks.wrap_y {
+                    break;
+                }
+                y %= height;
+            }
+
+            // Get the code of the line to draw, the clipped lines are not read
+            let mut code = 0u16;
+            for byte in 0..row_bytes {
+                let address = cpu.i.wrapping_add(k * row_bytes + byte);
+                code = (code << 8) | cpu.read_memory(address) as u16;
+            }
 
-            shift = 7;
-            for j in 0..8 {
+            for j in 0..columns {
                 // Get the abscissa of the pixel to draw
-                x = ((cpu.v[b3 as usize] + j) % LENGTH_WIDTH as u8) as u16;
+                let mut x = start_x + j;
+                if x >= width {
+                    if !cpu.quirks.wrap_x {
+                        break;
+                    }
+                    x %= width;
+                }
 
                 // Get the color of the pixel to draw
-                if ((code) & (0x1 << shift)) != 0 {
-                    let color = if pixels[(y * LENGTH_WIDTH + x) as usize].color == BLACK {
+                if code & (1 << (columns - 1 - j)) != 0 {
+                    let index = (y * width + x) as usize;
+                    let color = if screen.pixels[index].color == BLACK {
                         WHITE
                     } else {
                         cpu.v[0xF] = 1;
                         BLACK
                     };
-                    pixels[(y * LENGTH_WIDTH + x) as usize].color = color;
+                    screen.pixels[index].color = color;
+                    screen.dirty_rows[y as usize] = true;
                 }
-                shift -= 1;
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+
+    // Draw a sprite with DXYN at (x, y), X being V0 and Y being V1, and
+    // return VF
+    fn draw(screen: &mut Screen, x: u8, y: u8, rows: &[u8]) -> u8 {
+        draw_with(Quirks::new(), screen, x, y, rows)
+    }
+
+    fn draw_with(quirks: Quirks, screen: &mut Screen, x: u8, y: u8, rows: &[u8]) -> u8 {
+        sprite(quirks, screen, x, y, rows.len() as u16, rows).v[0xF]
+    }
+
+    // Draw a sprite with DXYN, N being 0 for 16x16 sprites, and return the
+    // CPU to check its registers and memory accesses
+    fn sprite(quirks: Quirks, screen: &mut Screen, x: u8, y: u8, n: u16, data: &[u8]) -> CPU {
+        let mut cpu = CPU::new();
+        cpu.quirks = quirks;
+        cpu.memory[0x300..0x300 + data.len()].copy_from_slice(data);
+        cpu.i = 0x300;
+        cpu.v[0] = x;
+        cpu.v[1] = y;
+        cpu.accesses.clear();
+        Pixel::draw_screen(&mut cpu, screen, n, 1, 0);
+        cpu
+    }
+
+    fn reads(cpu: &CPU) -> usize {
+        cpu.accesses.len()
+    }
+
+    fn hires_screen() -> Screen {
+        let mut screen = Screen::new();
+        screen.set_hires(true);
+        screen.presented();
+        screen
+    }
+
+    fn dirty_rows(screen: &Screen) -> Vec<usize> {
+        (0..screen.height as usize)
+            .filter(|row| screen.dirty_rows[*row])
+            .collect()
+    }
+
+    fn presented_screen() -> Screen {
+        let mut screen = Screen::new();
+        screen.presented();
+        screen
+    }
+
+    #[test]
+    fn draws_everything_the_first_time() {
+        let mut screen = Screen::new();
+        assert_eq!(dirty_rows(&screen).len(), LENGTH_HEIGHT as usize);
+
+        screen.presented();
+        assert!(!screen.is_dirty());
+    }
+
+    #[test]
+    fn sprites_mark_the_rows_they_change() {
+        let mut screen = presented_screen();
+
+        assert_eq!(draw(&mut screen, 10, 4, &[0x80, 0x00, 0x81]), 0);
+        assert_eq!(dirty_rows(&screen), [4, 6]);
+
+        screen.presented();
+        assert_eq!(dirty_rows(&screen), []);
+        // Erasing a sprite changes the same rows
+        assert_eq!(draw(&mut screen, 10, 4, &[0x80, 0x00, 0x81]), 1);
+        assert_eq!(dirty_rows(&screen), [4, 6]);
+    }
+
+    #[test]
+    fn clearing_marks_the_rows_which_were_on() {
+        let mut screen = presented_screen();
+        Pixel::clear_screen(&mut screen);
+        assert!(!screen.is_dirty());
+
+        draw(&mut screen, 0, 7, &[0xFF]);
+        draw(&mut screen, 63, 20, &[0x80]);
+        screen.presented();
+        Pixel::clear_screen(&mut screen);
+        assert_eq!(dirty_rows(&screen), [7, 20]);
+        assert!(screen.pixels.iter().all(|pixel| !pixel.is_on()));
+    }
+
+    fn lit_pixels(screen: &Screen) -> Vec<(u16, u16)> {
+        screen
+            .pixels
+            .iter()
+            .filter(|pixel| pixel.is_on())
+            .map(|pixel| (pixel.position.x, pixel.position.y))
+            .collect()
+    }
+
+    fn edges(edges: &str) -> Quirks {
+        let mut quirks = Quirks::new();
+        quirks.set_edges(edges).unwrap();
+        quirks
+    }
+
+    #[test]
+    fn wraps_the_starting_position() {
+        let mut screen = Screen::new();
+
+        draw(&mut screen, 64 + 3, 32 + 2, &[0x80]);
+        draw(&mut screen, 200, 100, &[0x80]);
+        assert_eq!(lit_pixels(&screen), [(3, 2), (8, 4)]);
+    }
+
+    #[test]
+    fn clips_at_the_right_edge() {
+        let mut screen = Screen::new();
+
+        assert_eq!(draw_with(edges("wrap-y"), &mut screen, 61, 0, &[0xFF]), 0);
+        assert_eq!(lit_pixels(&screen), [(61, 0), (62, 0), (63, 0)]);
+    }
+
+    #[test]
+    fn wraps_at_the_right_edge() {
+        let mut screen = Screen::new();
+
+        draw_with(edges("wrap-x"), &mut screen, 61, 0, &[0xF8]);
+        assert_eq!(
+            lit_pixels(&screen),
+            [(0, 0), (1, 0), (61, 0), (62, 0), (63, 0)]
+        );
+    }
+
+    #[test]
+    fn clips_at_the_bottom_edge() {
+        let mut screen = presented_screen();
+
+        draw_with(edges("wrap-x"), &mut screen, 5, 30, &[0x80, 0x80, 0x80]);
+        assert_eq!(lit_pixels(&screen), [(5, 30), (5, 31)]);
+        assert_eq!(dirty_rows(&screen), [30, 31]);
+    }
+
+    #[test]
+    fn wraps_at_the_bottom_edge() {
+        let mut screen = presented_screen();
+
+        draw_with(edges("wrap-y"), &mut screen, 5, 30, &[0x80, 0x80, 0x80]);
+        assert_eq!(lit_pixels(&screen), [(5, 0), (5, 30), (5, 31)]);
+        assert_eq!(dirty_rows(&screen), [0, 30, 31]);
+    }
+
+    #[test]
+    fn wraps_at_the_corner() {
+        let mut screen = Screen::new();
+
+        draw_with(edges("wrap"), &mut screen, 63, 31, &[0xC0, 0xC0]);
+        assert_eq!(lit_pixels(&screen), [(0, 0), (63, 0), (0, 31), (63, 31)]);
+    }
+
+    #[test]
+    fn only_visible_pixels_collide() {
+        let mut screen = Screen::new();
+        draw(&mut screen, 0, 0, &[0x80]);
+
+        // The second pixel of the sprite is off the right edge
+        assert_eq!(draw_with(edges("clip"), &mut screen, 63, 0, &[0x40]), 0);
+        assert_eq!(draw_with(edges("wrap"), &mut screen, 63, 0, &[0x40]), 1);
+        assert_eq!(lit_pixels(&screen), []);
+    }
+
+    #[test]
+    fn wraps_the_starting_position_in_high_resolution() {
+        let mut screen = hires_screen();
+
+        draw(&mut screen, 128 + 3, 64 + 2, &[0x80]);
+        draw(&mut screen, 200, 100, &[0x80]);
+        assert_eq!(lit_pixels(&screen), [(3, 2), (72, 36)]);
+    }
+
+    #[test]
+    fn clips_big_sprites_at_the_right_edge() {
+        let mut screen = hires_screen();
+
+        let cpu = sprite(edges("clip"), &mut screen, 120, 0, 0, &[0xFF; 32]);
+        assert_eq!(cpu.v[0xF], 0);
+        assert_eq!(reads(&cpu), 32);
+        let pixels = lit_pixels(&screen);
+        assert_eq!(pixels.len(), 8 * 16);
+        assert!(pixels.iter().all(|(x, _)| *x >= 120));
+    }
+
+    #[test]
+    fn wraps_big_sprites_at_the_right_edge() {
+        let mut screen = hires_screen();
+
+        sprite(edges("wrap"), &mut screen, 120, 0, 0, &[0xFF; 32]);
+        let pixels = lit_pixels(&screen);
+        assert_eq!(pixels.len(), 16 * 16);
+        assert!(pixels.iter().all(|(x, _)| *x >= 120 || *x < 8));
+    }
+
+    #[test]
+    fn clips_big_sprites_at_the_bottom_edge_without_reading_them() {
+        let mut screen = hires_screen();
+
+        let cpu = sprite(
+            edges("clip"),
+            &mut screen,
+            0,
+            60,
+            0,
+            &[0x80, 0x00].repeat(16),
+        );
+        // Only the 4 visible rows of 2 bytes are read
+        assert_eq!(reads(&cpu), 8);
+        assert_eq!(lit_pixels(&screen), [(0, 60), (0, 61), (0, 62), (0, 63)]);
+        assert_eq!(dirty_rows(&screen), [60, 61, 62, 63]);
+    }
+
+    #[test]
+    fn wraps_big_sprites_at_the_bottom_edge() {
+        let mut screen = hires_screen();
+
+        let cpu = sprite(
+            edges("wrap"),
+            &mut screen,
+            0,
+            60,
+            0,
+            &[0x80, 0x00].repeat(16),
+        );
+        assert_eq!(reads(&cpu), 32);
+        assert_eq!(lit_pixels(&screen).len(), 16);
+        assert_eq!(
+            dirty_rows(&screen),
+            (0..12).chain(60..64).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn draws_big_sprites_in_low_resolution() {
+        let mut screen = presented_screen();
+
+        // The first and the last column of every row
+        let data = [0x80, 0x01].repeat(16);
+        let cpu = sprite(edges("clip"), &mut screen, 56, 24, 0, &data);
+        assert_eq!(reads(&cpu), 16);
+        assert_eq!(
+            lit_pixels(&screen),
+            (24..32).map(|y| (56, y)).collect::<Vec<_>>()
+        );
+
+        let mut screen = presented_screen();
+        let cpu = sprite(edges("wrap"), &mut screen, 56, 24, 0, &data);
+        assert_eq!(reads(&cpu), 32);
+        assert_eq!(lit_pixels(&screen).len(), 32);
+        assert!(screen.is_on(7, 0) && screen.is_on(56, 31));
+    }
+
+    #[test]
+    fn big_sprites_collide() {
+        let mut screen = hires_screen();
+        draw(&mut screen, 25, 35, &[0x80]);
+
+        // Only the last pixel of the sprite is on
+        let mut data = [0; 32];
+        data[31] = 0x01;
+        assert_eq!(sprite(Quirks::new(), &mut screen, 0, 0, 0, &data).v[0xF], 0);
+        assert_eq!(
+            sprite(Quirks::new(), &mut screen, 10, 20, 0, &data).v[0xF],
+            1
+        );
+        assert_eq!(lit_pixels(&screen), [(15, 15)]);
+    }
+
+    #[test]
+    fn switching_the_resolution_clears_the_screen() {
+        let mut screen = presented_screen();
+        draw(&mut screen, 0, 0, &[0xFF]);
+        screen.presented();
+
+        screen.set_hires(true);
+        assert_eq!((screen.width(), screen.height()), (128, 64));
+        assert_eq!(dirty_rows(&screen).len(), 64);
+        assert_eq!(lit_pixels(&screen), []);
+
+        screen.set_hires(false);
+        assert_eq!((screen.width(), screen.height()), (64, 32));
+        assert_eq!(dirty_rows(&screen).len(), 32);
+    }
+
+    #[test]
+    fn scrolls_the_pixels() {
+        let mut screen = hires_screen();
+        draw(&mut screen, 10, 10, &[0x80]);
+        screen.presented();
+
+        screen.scroll(0, 5);
+        assert_eq!(lit_pixels(&screen), [(10, 15)]);
+        assert_eq!(dirty_rows(&screen), [10, 15]);
+
+        screen.scroll(4, 0);
+        screen.scroll(4, 0);
+        screen.scroll(-4, 0);
+        assert_eq!(lit_pixels(&screen), [(14, 15)]);
+
+        // Pixels scrolled out are lost
+        screen.scroll(0, 60);
+        screen.scroll(0, -60);
+        assert_eq!(lit_pixels(&screen), []);
+    }
+
+    fn viewport(scaling: Scaling, width: usize, height: usize) -> (usize, usize, usize, usize) {
+        let viewport = scaling.viewport(width, height, 64, 32);
+        (viewport.x, viewport.y, viewport.width, viewport.height)
+    }
+
+    #[test]
+    fn integer_scaling_letterboxes_the_rest() {
+        assert_eq!(viewport(Scaling::Integer, 640, 320), (0, 0, 640, 320));
+        // A scale of 3 fits the width but only 2 fits the height
+        assert_eq!(viewport(Scaling::Integer, 201, 75), (36, 5, 128, 64));
+        assert_eq!(viewport(Scaling::Integer, 333, 999), (6, 419, 320, 160));
+    }
+
+    #[test]
+    fn integer_scaling_keeps_one_window_pixel_per_chip8_pixel() {
+        // The part of the screen outside of a smaller window is cut
+        assert_eq!(viewport(Scaling::Integer, 50, 20), (0, 0, 64, 32));
+        assert_eq!(viewport(Scaling::Integer, 0, 0), (0, 0, 64, 32));
+    }
+
+    #[test]
+    fn aspect_scaling_fills_one_side() {
+        assert_eq!(viewport(Scaling::Aspect, 201, 75), (25, 0, 150, 75));
+        assert_eq!(viewport(Scaling::Aspect, 101, 999), (0, 474, 101, 50));
+        assert_eq!(viewport(Scaling::Aspect, 7, 3), (0, 0, 6, 3));
+        assert_eq!(viewport(Scaling::Aspect, 0, 0), (0, 0, 0, 0));
+    }
+
+    #[test]
+    fn latches_the_keys_pressed() {
+        let taps = Rc::new(RefCell::new(Vec::new()));
+        let mut latch = KeyLatch(Rc::clone(&taps));
+
+        // A tap between two frames is kept after the release
+        latch.set_key_state(Key::Q, true);
+        latch.set_key_state(Key::Q, false);
+        latch.set_key_state(Key::W, false);
+        assert_eq!(*taps.borrow(), [Key::Q]);
+    }
+
+    #[test]
+    fn high_resolution_uses_the_same_viewport() {
+        let hires = |scaling: Scaling, width, height| {
+            let viewport = scaling.viewport(width, height, 128, 64);
+            (viewport.x, viewport.y, viewport.width, viewport.height)
+        };
+
+        assert_eq!(hires(Scaling::Integer, 640, 320), (0, 0, 640, 320));
+        assert_eq!(hires(Scaling::Integer, 333, 999), (38, 435, 256, 128));
+        assert_eq!(hires(Scaling::Aspect, 201, 75), (25, 0, 150, 75));
+    }
+}
@@ -0,0 +1,103 @@
+//! # Profile
+//!
+//! Keymaps of the ROMs, stored in a directory as keymap files named after
+//! the SHA-1 of the ROM, so that the keymap of a game is found whatever the
+//! name of its file. The default directory is
+//! `$XDG_CONFIG_HOME/chip8-emulator/profiles`, or
+//! `~/.config/chip8-emulator/profiles`.
+
+use crate::keymap::Keymap;
+use std::path::PathBuf;
+
+pub struct Profiles {
+    directory: Option<PathBuf>,
+}
+
+impl Profiles {
+    /// Profiles in `directory`, or in the default directory.
+    pub fn new(directory: Option<&str>) -> Profiles {
+        let directory = match directory {
+            Some(directory) => Some(PathBuf::from(directory)),
+            None => std::env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .or_else(|| {
+                    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config"))
+                })
+                .map(|config| config.join("chip8-emulator").join("profiles")),
+        };
+
+        Profiles { directory }
+    }
+
+    fn path(&self, rom_hash: &str) -> Option<PathBuf> {
+        Some(
+            self.directory
+                .as_ref()?
+                .join(format!("{}.keymap", rom_hash)),
+        )
+    }
+
+    /// Keymap of a ROM, if it has a profile.
+    pub fn load(&self, rom_hash: &str) -> Option<Result<Keymap, String>> {
+        let path = self.path(rom_hash).filter(|path| path.exists())?;
+        Some(Keymap::load(&path))
+    }
+
+    /// Make `keymap` the profile of a ROM.
+    pub fn save(&self, rom_hash: &str, keymap: &Keymap) -> Result<(), String> {
+        let path = self
+            .path(rom_hash)
+            .ok_or("no directory to save the profile to")?;
+        let error = |error: std::io::Error| format!("cannot save {}: {}", path.display(), error);
+
+        if let Some(directory) = path.parent() {
+            std::fs::create_dir_all(directory).map_err(error)?;
+        }
+        std::fs::write(&path, keymap.to_string()).map_err(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Profiles in a directory of their own, removed by the test
+    fn profiles(name: &str) -> (Profiles, PathBuf) {
+        let directory =
+            std::env::temp_dir().join(format!("chip8-profiles-{}-{}", name, std::process::id()));
+        (Profiles::new(directory.to_str()), directory)
+    }
+
+    #[test]
+    fn loads_the_saved_keymaps() {
+        let (profiles, directory) = profiles("saved");
+        let keymap = Keymap::parse("5 = Up, W").unwrap();
+
+        profiles.save("abc", &keymap).unwrap();
+        let saved = profiles.load("abc");
+        let missing = profiles.load("def");
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(saved.unwrap().unwrap().to_string(), keymap.to_string());
+        assert!(missing.is_none());
+    }
+
+    #[test]
+    fn reports_malformed_profiles() {
+        let (profiles, directory) = profiles("malformed");
+        let path = directory.join("abc.keymap");
+
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(&path, "5 = Up\nnonsense\n").unwrap();
+        let loaded = profiles.load("abc");
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(
+            loaded.unwrap().err().unwrap(),
+            format!(
+                "{}: line 2: expected <chip8 key> = <host keys>",
+                path.display()
+            )
+        );
+    }
+}
@@ -0,0 +1,344 @@
+//! # Profiler
+//!
+//! Count the executed instructions per opcode, per address and per
+//! subroutine, and the frames which executed all the instructions of their
+//! budget without waiting for the vertical blank or a key, then print a
+//! summary once the emulation is over.
+//!
+//! A subroutine is the code run from a `2NNN` call until the matching return.
+//! Its inclusive count is the number of instructions executed during its
+//! calls, the subroutines it calls included, and its exclusive count the ones
+//! executed by its own code.
+
+use crate::cpu::{CPU, MEMORY_SIZE};
+use crate::monitor::Monitor;
+use crate::symbols::Symbols;
+use std::collections::HashMap;
+
+// Addresses listed in the summary
+const HOT_SPOTS: usize = 20;
+
+// Opcodes in the order of the actions of `crate::cpu::Jump`
+const OPCODES: [&str; 44] = [
+    "0NNN", "00E0", "00EE", "1NNN", "2NNN", "3XNN", "4XNN", "5XY0", "6XNN", "7XNN", "8XY0", "8XY1",
+    "8XY2", "8XY3", "8XY4", "8XY5", "8XY6", "8XY7", "8XYE", "9XY0", "ANNN", "BNNN", "CXNN", "DXYN",
+    "EX9E", "EXA1", "FX07", "FX0A", "FX15", "FX18", "FX1E", "FX29", "FX33", "FX55", "FX65", "00CN",
+    "00FB", "00FC", "00FD", "00FE", "00FF", "FX30", "FX75", "FX85",
+];
+
+// Action of FX0A, which is executed again until a key is released
+const WAIT_KEY: u16 = 27;
+
+struct Frame {
+    number: u64,
+    budget: u32,
+    instructions: u32,
+    // Whether the program waited for a key
+    waited: bool,
+}
+
+#[derive(Default)]
+struct Subroutine {
+    calls: u64,
+    inclusive: u64,
+    exclusive: u64,
+}
+
+pub struct Profiler {
+    instructions: u64,
+    opcodes: [u64; OPCODES.len()],
+    addresses: Vec<u64>,
+    subroutines: HashMap<u16, Subroutine>,
+    // Subroutines being executed, innermost last
+    stack: Vec<u16>,
+    frames: u64,
+    // Frames which executed their whole budget without waiting
+    exhausted: Vec<u64>,
+    // Frame being executed
+    frame: Option<Frame>,
+    // Address and action of the instruction about to be executed
+    pending: Option<(u16, u16)>,
+    symbols: Symbols,
+}
+
+impl Profiler {
+    pub fn new(symbols: Symbols) -> Profiler {
+        Profiler {
+            instructions: 0,
+            opcodes: [0; OPCODES.len()],
+            addresses: vec![0; MEMORY_SIZE],
+            subroutines: HashMap::new(),
+            stack: Vec::new(),
+            frames: 0,
+            exhausted: Vec::new(),
+            frame: None,
+            pending: None,
+            symbols,
+        }
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        count as f64 * 100.0 / self.instructions.max(1) as f64
+    }
+
+    fn print_opcodes(&self) {
+        let mut opcodes: Vec<(&str, u64)> = OPCODES
+            .iter()
+            .zip(self.opcodes)
+            .filter(|(_, count)| *count > 0)
+            .map(|(name, count)| (*name, count))
+            .collect();
+        opcodes.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+
+        println!("\nOpcodes:");
+        for (name, count) in opcodes {
+            println!("  {}  {:>10}  {:5.1}%", name, count, self.percent(count));
+        }
+    }
+
+    fn print_hot_spots(&self, cpu: &CPU) {
+        let mut addresses: Vec<usize> = (0..MEMORY_SIZE)
+            .filter(|address| self.addresses[*address] > 0)
+            .collect();
+        addresses.sort_by_key(|address| std::cmp::Reverse(self.addresses[*address]));
+
+        println!("\nHot spots:");
+        for address in addresses.into_iter().take(HOT_SPOTS) {
+            let opcode = ((cpu.memory[address] as u16) << 8)
+                | cpu.memory[(address + 1) % MEMORY_SIZE] as u16;
+            let count = self.addresses[address];
+            let line = format!(
+                "  {:03X}  {:<20}  {:>10}  {:5.1}%  {}",
+                address,
+                self.symbols.disassemble(&cpu.jp, opcode),
+                count,
+                self.percent(count),
+                self.symbols.locate(address as u16).unwrap_or_default()
+            );
+            println!("{}", line.trim_end());
+        }
+    }
+
+    fn print_subroutines(&self) {
+        let mut subroutines: Vec<(&u16, &Subroutine)> = self.subroutines.iter().collect();
+        subroutines.sort_by_key(|(address, subroutine)| {
+            (std::cmp::Reverse(subroutine.inclusive), **address)
+        });
+
+        println!("\nSubroutines:");
+        println!(
+            "  addr  {:>8}  {:>18}  {:>18}",
+            "calls", "inclusive", "exclusive"
+        );
+        for (address, subroutine) in subroutines {
+            let line = format!(
+                "  {:03X}   {:>8}  {:>10}  {:5.1}%  {:>10}  {:5.1}%  {}",
+                address,
+                subroutine.calls,
+                subroutine.inclusive,
+                self.percent(subroutine.inclusive),
+                subroutine.exclusive,
+                self.percent(subroutine.exclusive),
+                self.symbols.name(*address).unwrap_or_default()
+            );
+            println!("{}", line.trim_end());
+        }
+    }
+}
+
+/// List frames as ranges, e.g. `3-5, 10`.
+fn frame_ranges(frames: &[u64]) -> String {
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    for frame in frames {
+        match ranges.last_mut() {
+            Some((_, last)) if *last + 1 == *frame => *last = *frame,
+            _ => ranges.push((*frame, *frame)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|(first, last)| {
+            if first == last {
+                first.to_string()
+            } else {
+                format!("{}-{}", first, last)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+impl Monitor for Profiler {
+    fn start_frame(&mut self, frame: u64, budget: u32) {
+        self.frame = Some(Frame {
+            number: frame,
+            budget,
+            instructions: 0,
+            waited: false,
+        });
+        self.frames += 1;
+    }
+
+    fn before_instruction(&mut self, cpu: &CPU) -> bool {
+        self.pending = Some((cpu.pc, cpu.jp.get_action(cpu.get_opcode())));
+        true
+    }
+
+    fn after_instruction(&mut self, cpu: &CPU) {
+        let Some((address, action)) = self.pending.take() else {
+            return;
+        };
+        self.instructions += 1;
+        self.opcodes[action as usize] += 1;
+        self.addresses[address as usize % MEMORY_SIZE] += 1;
+        if let Some(frame) = &mut self.frame {
+            frame.instructions += 1;
+            frame.waited |= action == WAIT_KEY && cpu.pc == address;
+        }
+
+        // A call belongs to the caller and a return to the subroutine
+        for (depth, subroutine) in self.stack.iter().enumerate() {
+            // Recursive calls are only counted once
+            if !self.stack[..depth].contains(subroutine) {
+                self.subroutines.entry(*subroutine).or_default().inclusive += 1;
+            }
+        }
+        if let Some(subroutine) = self.stack.last() {
+            self.subroutines.entry(*subroutine).or_default().exclusive += 1;
+        }
+
+        // Follow the stack of the CPU rather than the opcodes, so that a
+        // return with an empty stack is ignored like the CPU does
+        let depth = cpu.number_jump as usize;
+        if depth > self.stack.len() {
+            self.stack.push(cpu.pc);
+            self.subroutines.entry(cpu.pc).or_default().calls += 1;
+        }
+        self.stack.truncate(depth);
+    }
+
+    fn end_frame(&mut self, cpu: &CPU) {
+        // A frame stopped by the vertical blank or spent waiting for a key had
+        // time left, whatever the instructions it executed
+        if let Some(frame) = self.frame.take() {
+            if frame.instructions >= frame.budget && !frame.waited && !cpu.waiting_vblank {
+                self.exhausted.push(frame.number);
+            }
+        }
+    }
+
+    fn finish(&mut self, cpu: &CPU) {
+        println!(
+            "Profile: {} instructions in {} frames",
+            self.instructions, self.frames
+        );
+        if self.exhausted.is_empty() {
+            println!("No frame used its whole budget without waiting");
+        } else {
+            println!(
+                "{} frames used their whole budget without waiting: {}",
+                self.exhausted.len(),
+                frame_ranges(&self.exhausted)
+            );
+        }
+
+        self.print_opcodes();
+        self.print_hot_spots(cpu);
+        self.print_subroutines();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixel::Screen;
+
+    fn run(profiler: &mut Profiler, rom: &[u8], instructions: usize) {
+        let mut cpu = CPU::new();
+        let mut screen = Screen::new();
+        cpu.memory[0x200..0x200 + rom.len()].copy_from_slice(rom);
+
+        for _ in 0..instructions {
+            profiler.before_instruction(&cpu);
+            cpu.execute_opcode(cpu.get_opcode(), &mut screen);
+            profiler.after_instruction(&cpu);
+        }
+    }
+
+    fn counts(profiler: &Profiler, address: u16) -> (u64, u64, u64) {
+        let subroutine = &profiler.subroutines[&address];
+        (subroutine.calls, subroutine.inclusive, subroutine.exclusive)
+    }
+
+    #[test]
+    fn counts_nested_subroutines() {
+        let mut profiler = Profiler::new(Symbols::default());
+        let rom = [
+            0x22, 0x06, // 200: CALL #206
+            0x00, 0xEE, // 202: RET with an empty stack
+            0x12, 0x04, // 204: JP #204
+            0x22, 0x0A, // 206: CALL #20A
+            0x00, 0xEE, // 208: RET
+            0x60, 0x01, // 20A: LD V0, #01
+            0x00, 0xEE, // 20C: RET
+        ];
+        run(&mut profiler, &rom, 8);
+
+        assert_eq!(profiler.instructions, 8);
+        assert_eq!(counts(&profiler, 0x206), (1, 4, 2));
+        assert_eq!(counts(&profiler, 0x20A), (1, 2, 2));
+        assert_eq!(profiler.subroutines.len(), 2);
+        assert!(profiler.stack.is_empty());
+    }
+
+    #[test]
+    fn counts_recursive_calls_once() {
+        let mut profiler = Profiler::new(Symbols::default());
+        let rom = [
+            0x22, 0x04, // 200: CALL #204
+            0x12, 0x02, // 202: JP #202
+            0x70, 0x01, // 204: ADD V0, #01
+            0x30, 0x03, // 206: SE V0, #03
+            0x22, 0x04, // 208: CALL #204
+            0x00, 0xEE, // 20A: RET
+        ];
+        run(&mut profiler, &rom, 13);
+
+        assert_eq!(counts(&profiler, 0x204), (3, 11, 11));
+        assert!(profiler.stack.is_empty());
+    }
+
+    #[test]
+    fn lists_the_exhausted_frames() {
+        let mut profiler = Profiler::new(Symbols::default());
+        let cpu = CPU::new();
+        // Frames 0 and 1 exhaust a budget of 2 instructions, not frame 2
+        for (frame, instructions) in [(0, 2), (1, 3), (2, 1)] {
+            profiler.start_frame(frame, 2);
+            run(&mut profiler, &[0x12, 0x00], instructions);
+            profiler.end_frame(&cpu);
+        }
+
+        assert_eq!(profiler.exhausted, [0, 1]);
+        assert_eq!(frame_ranges(&[3, 4, 5, 10, 12, 13]), "3-5, 10, 12-13");
+        assert_eq!(frame_ranges(&[]), "");
+    }
+    #[test]
+    fn does_not_count_waiting_frames_as_exhausted() {
+        let mut profiler = Profiler::new(Symbols::default());
+        let mut cpu = CPU::new();
+
+        // FX0A executed again while no key is released
+        profiler.start_frame(0, 2);
+        run(&mut profiler, &[0xF0, 0x0A], 2);
+        profiler.end_frame(&cpu);
+
+        // The last instruction of the budget waits for the vertical blank
+        profiler.start_frame(1, 2);
+        run(&mut profiler, &[0x12, 0x00], 2);
+        cpu.waiting_vblank = true;
+        profiler.end_frame(&cpu);
+
+        assert!(profiler.exhausted.is_empty());
+    }
+}
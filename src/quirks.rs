@@ -0,0 +1,88 @@
+//! # Quirks
+//!
+//! Behaviours which differ between Chip8 interpreters, and which some games
+//! rely on. A platform selects the behaviours of a family of interpreters.
+//! The SUPER-CHIP instructions are available whatever the platform.
+
+#[derive(Clone, Copy)]
+pub struct Quirks {
+    /// DXYN waits for the next vertical blank like on the COSMAC VIP, which
+    /// limits drawing to 60 sprites per second.
+    pub display_wait: bool,
+    /// Sprites crossing the right edge reappear on the left instead of
+    /// being clipped.
+    pub wrap_x: bool,
+    /// Sprites crossing the bottom edge reappear at the top instead of
+    /// being clipped.
+    pub wrap_y: bool,
+}
+
+impl Quirks {
+    pub fn new() -> Quirks {
+        Platform::Chip8.quirks()
+    }
+
+    /// Set how sprites behave at the edges of the screen: `clip`, `wrap`,
+    /// `wrap-x` (wrap horizontally only) or `wrap-y` (wrap vertically only).
+    pub fn set_edges(&mut self, edges: &str) -> Result<(), String> {
+        (self.wrap_x, self.wrap_y) = match edges {
+            "clip" => (false, false),
+            "wrap" => (true, true),
+            "wrap-x" => (true, false),
+            "wrap-y" => (false, true),
+            _ => return Err(format!("invalid edges: {}", edges)),
+        };
+
+        Ok(())
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks::new()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Platform {
+    /// Modern Chip8 interpreters.
+    Chip8,
+    /// The original interpreter of the COSMAC VIP.
+    Vip,
+    /// SUPER-CHIP on the HP48 calculators.
+    SuperChip,
+    /// XO-CHIP interpreters such as Octo.
+    XoChip,
+}
+
+impl Platform {
+    pub fn parse(name: &str) -> Option<Platform> {
+        match name {
+            "chip8" => Some(Platform::Chip8),
+            "vip" => Some(Platform::Vip),
+            "schip" => Some(Platform::SuperChip),
+            "xochip" => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            Platform::Chip8 | Platform::SuperChip => Quirks {
+                display_wait: false,
+                wrap_x: false,
+                wrap_y: false,
+            },
+            Platform::Vip => Quirks {
+                display_wait: true,
+                wrap_x: false,
+                wrap_y: false,
+            },
+            Platform::XoChip => Quirks {
+                display_wait: false,
+                wrap_x: true,
+                wrap_y: true,
+            },
+        }
+    }
+}
@@ -0,0 +1,219 @@
+//! # Symbols
+//!
+//! Names of the addresses of a program, read from a symbol file like the ones
+//! written by `chip8-asm`, with a `name = address` line per label:
+//!
+//! ```text
+//! ; Comments start with a semicolon
+//! main = 0x200
+//! main_loop = 0x20A
+//! ball = #3D0
+//! ```
+//!
+//! Addresses are hexadecimal with a `0x`, `#` or `$` prefix, or decimal.
+
+use crate::asm;
+use crate::cpu::Jump;
+use crate::disasm;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+#[derive(Clone, Default)]
+pub struct Symbols {
+    // Name shown for each address, the first one of the file
+    names: BTreeMap<u16, String>,
+    addresses: HashMap<String, u16>,
+}
+
+impl Symbols {
+    pub fn parse(text: &str) -> Result<Symbols, String> {
+        let mut symbols = Symbols::default();
+
+        for (number, line) in text.lines().enumerate() {
+            let error = |message: String| format!("line {}: {}", number + 1, message);
+            let line = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| error(format!("expected `name = address`: {}", line)))?;
+            let (name, value) = (name.trim(), value.trim());
+            if !asm::is_identifier(name) {
+                return Err(error(format!("invalid name: {}", name)));
+            }
+            let address = parse_address(value)
+                .filter(|address| *address < 0x1000)
+                .ok_or_else(|| error(format!("invalid address: {}", value)))?;
+            if symbols
+                .addresses
+                .insert(name.to_string(), address)
+                .is_some()
+            {
+                return Err(error(format!("{} is defined twice", name)));
+            }
+            symbols
+                .names
+                .entry(address)
+                .or_insert_with(|| name.to_string());
+        }
+
+        Ok(symbols)
+    }
+
+    pub fn load(path: &Path) -> Result<Symbols, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| format!("cannot read {}: {}", path.display(), error))?;
+        Symbols::parse(&text).map_err(|error| format!("{}: {}", path.display(), error))
+    }
+
+    /// Load the symbols of a ROM: the given symbol file, or else the file
+    /// next to the ROM with the `.sym` extension if there is one.
+    pub fn for_rom(rom_path: &str, path: Option<&str>) -> Result<Symbols, String> {
+        match path {
+            Some(path) => Symbols::load(Path::new(path)),
+            None => {
+                let path = Path::new(rom_path).with_extension("sym");
+                if path.is_file() {
+                    Symbols::load(&path)
+                } else {
+                    Ok(Symbols::default())
+                }
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    /// Name of an address.
+    pub fn name(&self, address: u16) -> Option<&str> {
+        self.names.get(&address).map(String::as_str)
+    }
+
+    /// Address of a name, which can be followed by a hexadecimal offset, e.g.
+    /// `main_loop+4`.
+    pub fn address(&self, text: &str) -> Option<u16> {
+        let (name, offset) = match text.split_once('+') {
+            Some((name, offset)) => (name, u16::from_str_radix(offset, 16).ok()?),
+            None => (text, 0),
+        };
+        self.addresses
+            .get(name)
+            .map(|address| address.wrapping_add(offset) & 0xFFF)
+    }
+
+    /// Closest name at or before an address, with the offset from it, e.g.
+    /// `main_loop+4`.
+    pub fn locate(&self, address: u16) -> Option<String> {
+        let (start, name) = self.names.range(..=address).next_back()?;
+        Some(match address - start {
+            0 => name.clone(),
+            offset => format!("{}+{:X}", name, offset),
+        })
+    }
+
+    /// Address followed by its location if it has one, e.g. `20E <main_loop+4>`.
+    pub fn describe(&self, address: u16) -> String {
+        match self.locate(address) {
+            Some(location) => format!("{:03X} <{}>", address, location),
+            None => format!("{:03X}", address),
+        }
+    }
+
+    /// Mnemonic of an instruction in the Cowgod syntax, naming the addresses
+    /// it refers to.
+    pub fn disassemble(&self, jp: &Jump, opcode: u16) -> String {
+        disasm::format_instruction(jp, opcode, disasm::Syntax::Cowgod, &|address| match self
+            .name(address)
+        {
+            Some(name) => name.to_string(),
+            None => format!("#{:03X}", address),
+        })
+    }
+}
+
+fn parse_address(text: &str) -> Option<u16> {
+    let hex = ["0x", "#", "$"]
+        .iter()
+        .find_map(|prefix| text.strip_prefix(prefix));
+    match hex {
+        Some(digits) => u16::from_str_radix(digits, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYMBOLS: &str = "; Comments start with a semicolon\n\
+                           main = 0x200\n\
+                           \n\
+                           main_loop = 0x20A ; the game\n\
+                           start = 512\n\
+                           ball = #3D0\n\
+                           paddle = $3E0\n";
+
+    #[test]
+    fn parses_symbol_files() {
+        let symbols = Symbols::parse(SYMBOLS).unwrap();
+
+        assert_eq!(symbols.address("main"), Some(0x200));
+        assert_eq!(symbols.address("start"), Some(0x200));
+        assert_eq!(symbols.address("ball"), Some(0x3D0));
+        assert_eq!(symbols.address("paddle"), Some(0x3E0));
+        assert_eq!(symbols.address("main_loop+4"), Some(0x20E));
+        assert_eq!(symbols.address("nothing"), None);
+        // The first name of an address is the one shown
+        assert_eq!(symbols.name(0x200), Some("main"));
+    }
+
+    #[test]
+    fn locates_addresses() {
+        let symbols = Symbols::parse(SYMBOLS).unwrap();
+
+        assert_eq!(symbols.locate(0x1FE), None);
+        assert_eq!(symbols.locate(0x20A).as_deref(), Some("main_loop"));
+        assert_eq!(symbols.describe(0x20E), "20E <main_loop+4>");
+        assert_eq!(symbols.describe(0x100), "100");
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        let error = |text| Symbols::parse(text).err().unwrap();
+
+        assert_eq!(
+            error("\nmain 0x200"),
+            "line 2: expected `name = address`: main 0x200"
+        );
+        assert_eq!(error("2main = 0x200"), "line 1: invalid name: 2main");
+        assert_eq!(error("main = 0x1000"), "line 1: invalid address: 0x1000");
+        assert_eq!(
+            error("main = 0x200\nmain = 0x202"),
+            "line 2: main is defined twice"
+        );
+    }
+
+    #[test]
+    fn names_the_addresses_of_instructions() {
+        let symbols = Symbols::parse(SYMBOLS).unwrap();
+
+        assert_eq!(symbols.disassemble(&Jump::new(), 0xA3D0), "LD I, ball");
+        assert_eq!(symbols.disassemble(&Jump::new(), 0x2300), "CALL #300");
+        assert_eq!(
+            disasm::listing(
+                &[0xA2, 0x04, 0x12, 0x00, 0x80],
+                disasm::Syntax::Cowgod,
+                &Symbols::parse("main = 0x200\nball = 0x204").unwrap()
+            ),
+            "main:\n\
+             200: A204  LD I, ball\n\
+             202: 1200  JP main\n\
+             ball:\n\
+             204: 80    DB #80  ; #.......\n"
+        );
+    }
+}
@@ -0,0 +1,440 @@
+//! # Terminal
+//!
+//! Frontend drawing the Chip8 screen directly in the terminal, for machines
+//! without a display (e.g. over SSH). The screen is drawn with Unicode
+//! characters, or as a bitmap on terminals supporting Sixel or Kitty graphics.
+
+use crate::frontend::Frontend;
+use crate::graphics::{self, Protocol, Rgb};
+use crate::keymap;
+use crate::osd::Osd;
+use crate::pixel::Screen;
+use crossterm::event::{
+    self, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::style::{Color, Colors, Print, ResetColor, SetColors};
+use crossterm::{cursor, execute, queue, terminal};
+use minifb::Key;
+use std::io::{Stdout, Write};
+use std::time::{Duration, Instant};
+
+const FRAME_DURATION: Duration = Duration::from_micros(1_000_000 / 60);
+
+// Most terminals only report key presses, and repeat them while the key is
+// held. Without a release event, a key is considered released when it has not
+// been repeated for this long, which must cover the auto-repeat delay (500 to
+// 660 ms by default on X11, GNOME and Windows).
+const KEY_HOLD: Duration = Duration::from_millis(700);
+
+/// ANSI colors of the pixels which are on and off.
+#[derive(Clone, Copy)]
+pub struct Palette {
+    pub foreground: Color,
+    pub background: Color,
+}
+
+impl Palette {
+    pub fn new() -> Palette {
+        Palette {
+            foreground: Color::White,
+            background: Color::Black,
+        }
+    }
+
+    /// Parse `<foreground>,<background>`, both being ANSI color names such
+    /// as `green` or `dark_blue`.
+    pub fn parse(text: &str) -> Result<Palette, String> {
+        let color =
+            |name: &str| Color::try_from(name).map_err(|_| format!("invalid color: {}", name));
+        let (foreground, background) = text
+            .split_once(',')
+            .ok_or(format!("invalid colors: {}", text))?;
+
+        Ok(Palette {
+            foreground: color(foreground)?,
+            background: color(background)?,
+        })
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::new()
+    }
+}
+
+/// Color of a bitmap pixel, as xterm shows the ANSI colors by default.
+fn rgb(color: Color) -> Rgb {
+    match color {
+        Color::Black => [0, 0, 0],
+        Color::DarkRed => [205, 0, 0],
+        Color::DarkGreen => [0, 205, 0],
+        Color::DarkYellow => [205, 205, 0],
+        Color::DarkBlue => [0, 0, 238],
+        Color::DarkMagenta => [205, 0, 205],
+        Color::DarkCyan => [0, 205, 205],
+        Color::Grey => [229, 229, 229],
+        Color::DarkGrey => [127, 127, 127],
+        Color::Red => [255, 0, 0],
+        Color::Green => [0, 255, 0],
+        Color::Yellow => [255, 255, 0],
+        Color::Blue => [92, 92, 255],
+        Color::Magenta => [255, 0, 255],
+        Color::Cyan => [0, 255, 255],
+        _ => [255, 255, 255],
+    }
+}
+
+/// How the Chip8 screen is drawn in the terminal.
+#[derive(Clone, Copy, PartialEq)]
+pub enum TerminalMode {
+    /// `▀` and `▄` characters, 1x2 pixels per character.
+    HalfBlock,
+    /// Braille patterns, 2x4 pixels per character.
+    Braille,
+    /// Bitmap with the Sixel protocol.
+    Sixel,
+    /// Bitmap with the Kitty graphics protocol.
+    Kitty,
+    /// Bitmap with the protocol the terminal supports, or half-blocks.
+    Auto,
+}
+
+impl TerminalMode {
+    pub fn parse(name: &str) -> Option<TerminalMode> {
+        match name {
+            "halfblock" => Some(TerminalMode::HalfBlock),
+            "braille" => Some(TerminalMode::Braille),
+            "sixel" => Some(TerminalMode::Sixel),
+            "kitty" => Some(TerminalMode::Kitty),
+            "auto" => Some(TerminalMode::Auto),
+            _ => None,
+        }
+    }
+}
+
+pub struct TerminalDisplay {
+    mode: TerminalMode,
+    scale: usize,
+    palette: Palette,
+    stdout: Stdout,
+    open: bool,
+    // Error which closed the frontend, reported once the terminal is restored
+    error: Option<std::io::Error>,
+    enhanced_keyboard: bool,
+    keys_down: Vec<(Key, Instant)>,
+    keys_released: Vec<Key>,
+    keys_pressed: Vec<Key>,
+    last_frame: Instant,
+    last_lines: Vec<String>,
+    // Size of the screen last drawn, the terminal being cleared when the
+    // program switches resolution
+    last_size: (u16, u16),
+}
+
+impl TerminalDisplay {
+    pub fn new(
+        mode: TerminalMode,
+        scale: usize,
+        palette: Palette,
+    ) -> Result<TerminalDisplay, String> {
+        let mut stdout = std::io::stdout();
+
+        // Fails when the standard input is not a terminal
+        terminal::enable_raw_mode()
+            .map_err(|error| format!("cannot use the terminal: {}", error))?;
+        if let Err(error) = execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide) {
+            let _ = terminal::disable_raw_mode();
+            return Err(format!("cannot use the terminal: {}", error));
+        }
+
+        let mode = match mode {
+            TerminalMode::Auto => match graphics::detect() {
+                Some(Protocol::Kitty) => TerminalMode::Kitty,
+                Some(Protocol::Sixel) => TerminalMode::Sixel,
+                None => TerminalMode::HalfBlock,
+            },
+            mode => mode,
+        };
+
+        // Terminals implementing the kitty keyboard protocol report key
+        // releases, which avoids guessing them from the auto-repeat
+        let enhanced_keyboard = terminal::supports_keyboard_enhancement().unwrap_or(false)
+            && execute!(
+                stdout,
+                PushKeyboardEnhancementFlags(
+                    KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+                        | KeyboardEnhancementFlags::REPORT_EVENT_TYPES
+                )
+            )
+            .is_ok();
+
+        Ok(TerminalDisplay {
+            mode,
+            scale,
+            palette,
+            stdout,
+            open: true,
+            error: None,
+            enhanced_keyboard,
+            keys_down: Vec::new(),
+            keys_released: Vec::new(),
+            keys_pressed: Vec::new(),
+            last_frame: Instant::now(),
+            last_lines: Vec::new(),
+            last_size: (0, 0),
+        })
+    }
+
+    fn read_events(&mut self) -> std::io::Result<()> {
+        self.keys_pressed.clear();
+
+        while event::poll(Duration::ZERO)? {
+            let Event::Key(event) = event::read()? else {
+                continue;
+            };
+
+            // Raw mode disables the interrupt signal, so handle Ctrl+C here
+            if event.code == KeyCode::Char('c') && event.modifiers.contains(KeyModifiers::CONTROL) {
+                self.open = false;
+                continue;
+            }
+
+            let Some(key) = to_key(event.code) else {
+                continue;
+            };
+
+            match event.kind {
+                KeyEventKind::Press | KeyEventKind::Repeat => {
+                    // Without release events, repeated presses look the same
+                    // as the first one, so tell them by the key being down
+                    if !self.keys_down.iter().any(|(down, _)| *down == key) {
+                        self.keys_pressed.push(key);
+                    }
+                    self.keys_down.retain(|(down, _)| *down != key);
+                    self.keys_down.push((key, Instant::now()));
+                }
+                KeyEventKind::Release => self.release(key),
+            }
+        }
+
+        if !self.enhanced_keyboard {
+            let expired: Vec<Key> = self
+                .keys_down
+                .iter()
+                .filter(|(_, time)| time.elapsed() > KEY_HOLD)
+                .map(|(key, _)| *key)
+                .collect();
+
+            for key in expired {
+                self.release(key);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn release(&mut self, key: Key) {
+        self.keys_down.retain(|(down, _)| *down != key);
+        self.keys_released.push(key);
+    }
+
+    fn render(&self, screen: &Screen) -> Vec<String> {
+        let (width, height) = (screen.width(), screen.height());
+        let on = |x: u16, y: u16| screen.is_on(x, y);
+        let mut lines = Vec::new();
+
+        match self.mode {
+            TerminalMode::HalfBlock => {
+                for y in (0..height).step_by(2) {
+                    let line = (0..width)
+                        .map(|x| match (on(x, y), on(x, y + 1)) {
+                            (true, true) => '█',
+                            (true, false) => '▀',
+                            (false, true) => '▄',
+                            (false, false) => ' ',
+                        })
+                        .collect();
+                    lines.push(line);
+                }
+            }
+            TerminalMode::Braille => {
+                // Bit of each dot of a Braille pattern, indexed by [row][column]
+                const DOTS: [[u32; 2]; 4] =
+                    [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+                for y in (0..height).step_by(4) {
+                    let line = (0..width)
+                        .step_by(2)
+                        .map(|x| {
+                            let mut pattern = 0;
+                            for (row, bits) in DOTS.iter().enumerate() {
+                                for (column, bit) in bits.iter().enumerate() {
+                                    if on(x + column as u16, y + row as u16) {
+                                        pattern |= bit;
+                                    }
+                                }
+                            }
+                            char::from_u32(0x2800 + pattern).unwrap()
+                        })
+                        .collect();
+                    lines.push(line);
+                }
+            }
+            // A bitmap is a single escape sequence drawn from the top left corner
+            TerminalMode::Sixel => lines.push(graphics::sixel(screen, self.scale, self.colors())),
+            TerminalMode::Kitty => lines.push(graphics::kitty(screen, self.scale, self.colors())),
+            TerminalMode::Auto => unreachable!("resolved when the terminal is opened"),
+        }
+
+        lines
+    }
+
+    /// Colors of the bitmap pixels which are on and off.
+    fn colors(&self) -> [Rgb; 2] {
+        [rgb(self.palette.foreground), rgb(self.palette.background)]
+    }
+
+    fn present(&mut self, screen: &Screen) -> std::io::Result<()> {
+        // The lines of another resolution do not cover the previous ones
+        let size = (screen.width(), screen.height());
+        if size != self.last_size {
+            queue!(self.stdout, terminal::Clear(terminal::ClearType::All))?;
+            self.last_size = size;
+            self.last_lines.clear();
+        }
+
+        // Only write the lines which changed, terminals are slow to repaint
+        let lines = self.render(screen);
+        let colors = Colors::new(self.palette.foreground, self.palette.background);
+        for (row, line) in lines.iter().enumerate() {
+            if self.last_lines.get(row) != Some(line) {
+                queue!(
+                    self.stdout,
+                    cursor::MoveTo(0, row as u16),
+                    SetColors(colors),
+                    Print(line),
+                    ResetColor
+                )?;
+            }
+        }
+        self.stdout.flush()?;
+        self.last_lines = lines;
+
+        Ok(())
+    }
+}
+
+impl Frontend for TerminalDisplay {
+    fn draw(&mut self, screen: &Screen, _osd: &Osd) {
+        // A terminal which cannot be read or written, e.g. a closed SSH
+        // connection, ends the run
+        let mut result = self.read_events();
+        if result.is_ok() && screen.is_dirty() {
+            result = self.present(screen);
+        }
+        if let Err(error) = result {
+            self.open = false;
+            self.error = Some(error);
+        }
+
+        // Run at 60 frames per second like the window
+        let elapsed = self.last_frame.elapsed();
+        if elapsed < FRAME_DURATION {
+            std::thread::sleep(FRAME_DURATION - elapsed);
+        }
+        self.last_frame = Instant::now();
+    }
+
+    fn is_open(&self) -> bool {
+        self.open
+    }
+
+    fn get_keys_down(&mut self) -> Vec<Key> {
+        let mut keys: Vec<Key> = self.keys_down.iter().map(|(key, _)| *key).collect();
+        // Keys released since the last frame still count once, so that short
+        // taps are not missed
+        keys.append(&mut self.keys_released);
+        keys
+    }
+
+    fn is_key_pressed(&mut self, key: Key) -> bool {
+        self.keys_pressed.contains(&key)
+    }
+}
+
+impl Drop for TerminalDisplay {
+    fn drop(&mut self) {
+        if self.enhanced_keyboard {
+            let _ = execute!(self.stdout, PopKeyboardEnhancementFlags);
+        }
+        if self.mode == TerminalMode::Kitty {
+            // Delete the image, which would otherwise stay on the screen
+            let _ = execute!(self.stdout, Print("\x1b_Ga=d,d=A,q=2\x1b\\"));
+        }
+        let _ = execute!(self.stdout, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+
+        if let Some(error) = &self.error {
+            println!("terminal error: {}", error);
+        }
+    }
+}
+
+/// Translate a terminal key to the window key it stands for, as if typed on
+/// a QWERTY keyboard.
+fn to_key(code: KeyCode) -> Option<Key> {
+    let key = match code {
+        KeyCode::Esc => Key::Escape,
+        KeyCode::Enter => Key::Enter,
+        KeyCode::Tab => Key::Tab,
+        KeyCode::Backspace => Key::Backspace,
+        KeyCode::Delete => Key::Delete,
+        KeyCode::Insert => Key::Insert,
+        KeyCode::Home => Key::Home,
+        KeyCode::End => Key::End,
+        KeyCode::PageUp => Key::PageUp,
+        KeyCode::PageDown => Key::PageDown,
+        KeyCode::Up => Key::Up,
+        KeyCode::Down => Key::Down,
+        KeyCode::Left => Key::Left,
+        KeyCode::Right => Key::Right,
+        KeyCode::F(number) => return keymap::parse_key(&format!("F{}", number)),
+        KeyCode::Char(' ') => Key::Space,
+        KeyCode::Char('\'') | KeyCode::Char('"') => Key::Apostrophe,
+        KeyCode::Char('`') | KeyCode::Char('~') => Key::Backquote,
+        KeyCode::Char('\\') | KeyCode::Char('|') => Key::Backslash,
+        KeyCode::Char(',') | KeyCode::Char('<') => Key::Comma,
+        KeyCode::Char('=') | KeyCode::Char('+') => Key::Equal,
+        KeyCode::Char('[') | KeyCode::Char('{') => Key::LeftBracket,
+        KeyCode::Char('-') | KeyCode::Char('_') => Key::Minus,
+        KeyCode::Char('.') | KeyCode::Char('>') => Key::Period,
+        KeyCode::Char(']') | KeyCode::Char('}') => Key::RightBracket,
+        KeyCode::Char(';') | KeyCode::Char(':') => Key::Semicolon,
+        KeyCode::Char('/') | KeyCode::Char('?') => Key::Slash,
+        // Digits and letters
+        KeyCode::Char(character) if character.is_ascii_alphanumeric() => {
+            return keymap::parse_key(&character.to_string())
+        }
+        _ => return None,
+    };
+
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_palette() {
+        let palette = Palette::parse("green,dark_blue").unwrap();
+        assert_eq!(palette.foreground, Color::Green);
+        assert_eq!(palette.background, Color::DarkBlue);
+        assert_eq!(rgb(palette.background), [0, 0, 238]);
+
+        assert!(Palette::parse("green").is_err());
+        assert!(Palette::parse("green,pink").is_err());
+    }
+}
@@ -0,0 +1,227 @@
+//! # Trace
+//!
+//! Log of the executed instructions, one line per instruction with the state
+//! of the CPU before it:
+//!
+//! ```text
+//! cycle=42 pc=20E op=3A40 SE VA, #40           v=00000000000000000000240600000500 i=30C sp=0 dt=00 st=00
+//! ```
+//!
+//! The cycle counts the instructions executed since the start, traced or not.
+//! With symbols, the instructions name the addresses they refer to, and the
+//! labelled instructions are preceded by a `name:` line.
+//! The format only depends on the state of the CPU, so that the traces of two
+//! builds or two emulators can be compared with `diff` to find where they
+//! diverge.
+
+use crate::cpu::CPU;
+use crate::monitor::Monitor;
+use crate::symbols::Symbols;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+pub struct Trace {
+    file: BufWriter<File>,
+    /// Addresses of the traced instructions, inclusive.
+    range: Option<(u16, u16)>,
+    /// Traced frames, inclusive.
+    frames: Option<(u64, u64)>,
+    cycle: u64,
+    frame: u64,
+    // Line of the instruction about to be executed, written once it is
+    pending: Option<String>,
+    // Stop tracing after a write error
+    failed: bool,
+    symbols: Symbols,
+}
+
+impl Trace {
+    pub fn create(
+        path: &str,
+        range: Option<(u16, u16)>,
+        frames: Option<(u64, u64)>,
+        symbols: Symbols,
+    ) -> Result<Trace, String> {
+        let file =
+            File::create(path).map_err(|error| format!("cannot create {}: {}", path, error))?;
+
+        Ok(Trace {
+            file: BufWriter::new(file),
+            range,
+            frames,
+            cycle: 0,
+            frame: 0,
+            pending: None,
+            failed: false,
+            symbols,
+        })
+    }
+
+    fn is_traced(&self, pc: u16) -> bool {
+        !self.failed
+            && self
+                .range
+                .is_none_or(|(start, end)| (start..=end).contains(&pc))
+            && self
+                .frames
+                .is_none_or(|(first, last)| (first..=last).contains(&self.frame))
+    }
+}
+
+impl Monitor for Trace {
+    fn start_frame(&mut self, frame: u64, _budget: u32) {
+        self.frame = frame;
+    }
+
+    fn before_instruction(&mut self, cpu: &CPU) -> bool {
+        self.pending = self.is_traced(cpu.pc).then(|| {
+            let opcode = cpu.get_opcode();
+            let registers: String = cpu.v.iter().map(|v| format!("{:02X}", v)).collect();
+            let label = match self.symbols.name(cpu.pc) {
+                Some(name) => format!("{}:\n", name),
+                None => String::new(),
+            };
+            format!(
+                "{}cycle={} pc={:03X} op={:04X} {:<20} v={} i={:03X} sp={:X} dt={:02X} st={:02X}",
+                label,
+                self.cycle,
+                cpu.pc,
+                opcode,
+                self.symbols.disassemble(&cpu.jp, opcode),
+                registers,
+                cpu.i,
+                cpu.number_jump,
+                cpu.game_counter,
+                cpu.sound_counter
+            )
+        });
+        true
+    }
+
+    fn after_instruction(&mut self, _cpu: &CPU) {
+        if let Some(line) = self.pending.take() {
+            // A trace missing lines is useless, but the emulation can go on
+            if let Err(error) = writeln!(self.file, "{}", line) {
+                println!("cannot write the trace: {}", error);
+                self.failed = true;
+            }
+        }
+        self.cycle += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixel::Screen;
+
+    // LD V0, #05 then a loop adding 1 to V0
+    const ROM: [u8; 6] = [0x60, 0x05, 0x70, 0x01, 0x12, 0x02];
+
+    /// Trace two instructions per frame, returning the lines written.
+    fn trace(
+        name: &str,
+        range: Option<(u16, u16)>,
+        frames: Option<(u64, u64)>,
+        symbols: Symbols,
+    ) -> Vec<String> {
+        let path =
+            std::env::temp_dir().join(format!("chip8-trace-{}-{}", name, std::process::id()));
+        let mut trace = Trace::create(path.to_str().unwrap(), range, frames, symbols).unwrap();
+        let mut cpu = CPU::new();
+        let mut screen = Screen::new();
+        cpu.memory[0x200..0x200 + ROM.len()].copy_from_slice(&ROM);
+
+        for frame in 0..3 {
+            trace.start_frame(frame, 2);
+            for _ in 0..2 {
+                trace.before_instruction(&cpu);
+                cpu.execute_opcode(cpu.get_opcode(), &mut screen);
+                trace.after_instruction(&cpu);
+            }
+        }
+        drop(trace);
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn writes_the_state_before_each_instruction() {
+        let lines = trace("all", None, None, Symbols::default());
+
+        assert_eq!(lines.len(), 6);
+        assert_eq!(
+            lines[0],
+            "cycle=0 pc=200 op=6005 LD V0, #05           \
+             v=00000000000000000000000000000000 i=000 sp=0 dt=00 st=00"
+        );
+        assert_eq!(
+            lines[3],
+            "cycle=3 pc=202 op=7001 ADD V0, #01          \
+             v=06000000000000000000000000000000 i=000 sp=0 dt=00 st=00"
+        );
+    }
+
+    #[test]
+    fn filters_addresses_and_frames() {
+        let cycles = |lines: Vec<String>| -> Vec<String> {
+            lines
+                .iter()
+                .map(|line| line.split(' ').take(2).collect::<Vec<_>>().join(" "))
+                .collect()
+        };
+
+        assert_eq!(
+            cycles(trace(
+                "range",
+                Some((0x202, 0x203)),
+                None,
+                Symbols::default()
+            )),
+            ["cycle=1 pc=202", "cycle=3 pc=202", "cycle=5 pc=202"]
+        );
+        assert_eq!(
+            cycles(trace("frames", None, Some((1, 2)), Symbols::default())),
+            [
+                "cycle=2 pc=204",
+                "cycle=3 pc=202",
+                "cycle=4 pc=204",
+                "cycle=5 pc=202"
+            ]
+        );
+        assert_eq!(
+            cycles(trace(
+                "both",
+                Some((0x204, 0x204)),
+                Some((1, 1)),
+                Symbols::default()
+            )),
+            ["cycle=2 pc=204"]
+        );
+    }
+
+    #[test]
+    fn writes_labels_before_their_instructions() {
+        let symbols = Symbols::parse("main_loop = 0x202").unwrap();
+        let lines = trace("labels", Some((0x202, 0x205)), Some((0, 1)), symbols);
+
+        // Instructions without the registers
+        let starts: Vec<&str> = lines
+            .iter()
+            .map(|line| line.split(" v=").next().unwrap().trim_end())
+            .collect();
+
+        assert_eq!(
+            starts,
+            [
+                "main_loop:",
+                "cycle=1 pc=202 op=7001 ADD V0, #01",
+                "cycle=2 pc=204 op=1202 JP main_loop",
+                "main_loop:",
+                "cycle=3 pc=202 op=7001 ADD V0, #01",
+            ]
+        );
+    }
+}
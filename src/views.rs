@@ -0,0 +1,294 @@
+//! # Views
+//!
+//! Debugger windows opened next to the game window and updated at every
+//! frame: a hex view of the memory highlighting the bytes recently written,
+//! the registers and the call stack, and the disassembly around the program
+//! counter. The memory view scrolls with the mouse wheel or the arrow and
+//! page keys.
+
+use crate::cpu::{AccessKind, CPU, MEMORY_SIZE};
+use crate::font::{self, Canvas};
+use crate::monitor::Monitor;
+use crate::symbols::Symbols;
+use minifb::{Key, KeyRepeat, Window, WindowOptions};
+
+const SCALE: usize = 2;
+// Size of a character in window pixels
+const CELL_WIDTH: usize = (font::GLYPH_WIDTH + 1) * SCALE;
+const CELL_HEIGHT: usize = (font::GLYPH_HEIGHT + 2) * SCALE;
+// Window pixels around the text
+const PADDING: usize = 4;
+
+const BACKGROUND_COLOR: u32 = 0x101010;
+const TEXT_COLOR: u32 = 0xC0C0C0;
+const LABEL_COLOR: u32 = 0x808080;
+const PC_COLOR: u32 = 0x204060;
+const I_COLOR: u32 = 0x206040;
+const WRITE_COLOR: u32 = 0xC04020;
+
+// Frames a written byte stays highlighted, fading out
+const WRITE_FRAMES: u32 = 60;
+
+const BYTES_PER_ROW: usize = 16;
+const MEMORY_ROWS: usize = 32;
+// Address, then the bytes separated by a space
+const MEMORY_COLUMNS: usize = 5 + BYTES_PER_ROW * 3 - 1;
+
+// Registers, then the names of the return addresses
+const REGISTER_COLUMNS: usize = 24;
+const REGISTER_ROWS: usize = 13 + 16;
+
+// Odd so that the program counter is in the middle
+const DISASSEMBLY_ROWS: usize = 31;
+// Column of the names of the labelled addresses
+const DISASSEMBLY_LABEL: usize = 31;
+const DISASSEMBLY_COLUMNS: usize = DISASSEMBLY_LABEL + 14;
+
+/// Window showing text on a grid of characters.
+struct TextWindow {
+    window: Window,
+    buffer: Vec<u32>,
+    width: usize,
+    height: usize,
+}
+
+impl TextWindow {
+    fn open(title: &str, columns: usize, rows: usize) -> Result<TextWindow, String> {
+        let width = columns * CELL_WIDTH + 2 * PADDING;
+        let height = rows * CELL_HEIGHT + 2 * PADDING;
+        let mut window = Window::new(title, width, height, WindowOptions::default())
+            .map_err(|error| format!("cannot open the {} window: {}", title, error))?;
+        // The game window already paces the frames
+        window.limit_update_rate(None);
+
+        Ok(TextWindow {
+            window,
+            buffer: vec![BACKGROUND_COLOR; width * height],
+            width,
+            height,
+        })
+    }
+
+    fn canvas(&mut self) -> Canvas<'_> {
+        self.buffer.fill(BACKGROUND_COLOR);
+        Canvas {
+            buffer: &mut self.buffer,
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    fn present(&mut self) {
+        // A view failing to update is not worth stopping the emulation
+        let _ = self
+            .window
+            .update_with_buffer(&self.buffer, self.width, self.height);
+    }
+}
+
+fn text(canvas: &mut Canvas, column: usize, row: usize, text: &str, color: u32) {
+    let x = PADDING + column * CELL_WIDTH;
+    let y = PADDING + row * CELL_HEIGHT + SCALE;
+    canvas.draw_text(x, y, text, SCALE, color);
+}
+
+/// Fill the background of `length` characters.
+fn highlight(canvas: &mut Canvas, column: usize, row: usize, length: usize, color: u32) {
+    let x = PADDING + column * CELL_WIDTH - SCALE;
+    let y = PADDING + row * CELL_HEIGHT;
+    canvas.fill_rect(x, y, length * CELL_WIDTH + SCALE, CELL_HEIGHT, color);
+}
+
+/// Mix two colors, `amount` going from 0 (`from`) to `total` (`to`).
+fn blend(from: u32, to: u32, amount: u32, total: u32) -> u32 {
+    let channel = |shift: u32| {
+        let from = (from >> shift) & 0xFF;
+        let to = (to >> shift) & 0xFF;
+        ((from * (total - amount) + to * amount) / total) << shift
+    };
+    channel(16) | channel(8) | channel(0)
+}
+
+pub struct Views {
+    memory: Option<TextWindow>,
+    registers: Option<TextWindow>,
+    disassembly: Option<TextWindow>,
+    // Frames since each byte was last written
+    writes: Vec<u32>,
+    // First row of the memory view
+    memory_row: usize,
+    symbols: Symbols,
+}
+
+impl Views {
+    pub fn open(symbols: Symbols) -> Result<Views, String> {
+        Ok(Views {
+            memory: Some(TextWindow::open("Memory", MEMORY_COLUMNS, MEMORY_ROWS)?),
+            registers: Some(TextWindow::open(
+                "Registers",
+                REGISTER_COLUMNS,
+                REGISTER_ROWS,
+            )?),
+            disassembly: Some(TextWindow::open(
+                "Disassembly",
+                DISASSEMBLY_COLUMNS,
+                DISASSEMBLY_ROWS,
+            )?),
+            writes: vec![WRITE_FRAMES; MEMORY_SIZE],
+            // The program
+            memory_row: 0x200 / BYTES_PER_ROW,
+            symbols,
+        })
+    }
+
+    fn scroll_memory(&mut self, window: &Window) {
+        let mut rows = 0;
+        if let Some((_, wheel)) = window.get_scroll_wheel() {
+            rows -= wheel.signum() as isize * 2;
+        }
+        for (key, delta) in [
+            (Key::Up, -1),
+            (Key::Down, 1),
+            (Key::PageUp, -(MEMORY_ROWS as isize)),
+            (Key::PageDown, MEMORY_ROWS as isize),
+        ] {
+            if window.is_key_pressed(key, KeyRepeat::Yes) {
+                rows += delta;
+            }
+        }
+
+        let last_row = MEMORY_SIZE / BYTES_PER_ROW - MEMORY_ROWS;
+        self.memory_row = self.memory_row.saturating_add_signed(rows).min(last_row);
+    }
+
+    fn draw_memory(&self, canvas: &mut Canvas, cpu: &CPU) {
+        for row in 0..MEMORY_ROWS {
+            let start = (self.memory_row + row) * BYTES_PER_ROW;
+            text(canvas, 0, row, &format!("{:03X}:", start), LABEL_COLOR);
+
+            for index in 0..BYTES_PER_ROW {
+                let address = start + index;
+                let column = 5 + index * 3;
+                let age = self.writes[address];
+
+                if address == cpu.pc as usize || address == cpu.pc as usize + 1 {
+                    highlight(canvas, column, row, 2, PC_COLOR);
+                } else if address == cpu.i as usize {
+                    highlight(canvas, column, row, 2, I_COLOR);
+                }
+                if age < WRITE_FRAMES {
+                    let color = blend(WRITE_COLOR, BACKGROUND_COLOR, age, WRITE_FRAMES);
+                    highlight(canvas, column, row, 2, color);
+                }
+                let byte = format!("{:02X}", cpu.memory[address]);
+                text(canvas, column, row, &byte, TEXT_COLOR);
+            }
+        }
+    }
+}
+
+fn draw_registers(canvas: &mut Canvas, cpu: &CPU, symbols: &Symbols) {
+    let mut field = |column: usize, row: usize, name: &str, value: String| {
+        text(canvas, column, row, name, LABEL_COLOR);
+        text(canvas, column + 3, row, &value, TEXT_COLOR);
+    };
+
+    for (index, value) in cpu.v.iter().enumerate() {
+        let (column, row) = if index < 8 {
+            (0, index)
+        } else {
+            (8, index - 8)
+        };
+        field(
+            column,
+            row,
+            &format!("V{:X}", index),
+            format!("{:02X}", value),
+        );
+    }
+    field(0, 9, "I", format!("{:03X}", cpu.i));
+    field(8, 9, "PC", format!("{:03X}", cpu.pc));
+    field(0, 10, "DT", format!("{:02X}", cpu.game_counter));
+    field(8, 10, "ST", format!("{:02X}", cpu.sound_counter));
+    field(0, 12, "SP", format!("{:X}", cpu.number_jump));
+
+    // Innermost call first
+    for (row, depth) in (0..cpu.number_jump as usize).rev().enumerate() {
+        field(
+            0,
+            13 + row,
+            &format!("{:X}", depth),
+            format!("{:03X}", cpu.jump[depth]),
+        );
+    }
+    for (row, depth) in (0..cpu.number_jump as usize).rev().enumerate() {
+        if let Some(location) = symbols.locate(cpu.jump[depth]) {
+            text(canvas, 7, 13 + row, &location, LABEL_COLOR);
+        }
+    }
+}
+
+fn draw_disassembly(canvas: &mut Canvas, cpu: &CPU, symbols: &Symbols) {
+    let middle = DISASSEMBLY_ROWS / 2;
+    for row in 0..DISASSEMBLY_ROWS {
+        let address = (cpu.pc as usize + MEMORY_SIZE * 2 + row * 2 - middle * 2) % MEMORY_SIZE;
+        let opcode =
+            ((cpu.memory[address] as u16) << 8) | cpu.memory[(address + 1) % MEMORY_SIZE] as u16;
+
+        if row == middle {
+            highlight(canvas, 0, row, DISASSEMBLY_COLUMNS, PC_COLOR);
+        }
+        text(canvas, 0, row, &format!("{:03X}:", address), LABEL_COLOR);
+        let line = format!("{:04X}  {}", opcode, symbols.disassemble(&cpu.jp, opcode));
+        text(canvas, 5, row, &line, TEXT_COLOR);
+        if let Some(name) = symbols.name(address as u16) {
+            text(canvas, DISASSEMBLY_LABEL, row, name, LABEL_COLOR);
+        }
+    }
+}
+
+/// Redraw a view, forgetting it once closed.
+fn update(view: &mut Option<TextWindow>, draw: impl FnOnce(&mut Canvas)) {
+    if let Some(window) = view {
+        draw(&mut window.canvas());
+        window.present();
+        if !window.window.is_open() {
+            *view = None;
+        }
+    }
+}
+
+impl Monitor for Views {
+    fn start_frame(&mut self, _frame: u64, _budget: u32) {
+        // Highlights only fade while the emulation advances, to stay visible
+        // while it is stopped
+        for age in &mut self.writes {
+            *age = (*age + 1).min(WRITE_FRAMES);
+        }
+    }
+
+    fn after_instruction(&mut self, cpu: &CPU) {
+        for access in &cpu.accesses {
+            if access.kind == AccessKind::Write {
+                self.writes[access.address as usize % MEMORY_SIZE] = 0;
+            }
+        }
+    }
+
+    fn draw(&mut self, cpu: &CPU) {
+        let mut memory = self.memory.take();
+        if let Some(view) = &memory {
+            self.scroll_memory(&view.window);
+        }
+        update(&mut memory, |canvas| self.draw_memory(canvas, cpu));
+        self.memory = memory;
+
+        let symbols = &self.symbols;
+        update(&mut self.registers, |canvas| {
+            draw_registers(canvas, cpu, symbols)
+        });
+        update(&mut self.disassembly, |canvas| {
+            draw_disassembly(canvas, cpu, symbols)
+        });
+    }
+}